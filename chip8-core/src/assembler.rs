@@ -0,0 +1,678 @@
+/*
+Assembler for the dialect printed by the disassembler: Cowgod's mnemonics plus the
+SUPER-CHIP and XO-CHIP ones, case insensitive.
+
+    ; comment
+    include "sprites.asm"       ; pasted in place, path relative to the including file
+    SPEED EQU 3                 ; constant
+    start:                      ; label, may also be followed by an instruction
+        LD V0, SPEED
+        LD I, ball
+        DRW V0, V1, 1
+        JP start
+    ball:
+        DB 0x80, 0b10000000     ; bytes
+        DW #1234                ; big endian words
+
+Numbers are decimal, 0x/# hexadecimal or 0b binary. Operands accept sums and differences
+of numbers, labels and constants like `ball + 2`.
+*/
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use instruction::Instruction;
+
+const ROM_BASE_ADDRESS : u32 = 0x200;
+const MAX_INCLUDE_DEPTH : usize = 16;
+const MAX_CONSTANT_DEPTH : usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError
+{
+    pub file : String,
+    pub line : usize,
+    pub message : String,
+}
+
+impl fmt::Display for AssembleError
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl error::Error for AssembleError
+{
+}
+
+struct SourceLine
+{
+    file : String,
+    line : usize,
+    label : Option<String>,
+    mnemonic : String,
+    operands : Vec<String>,
+}
+
+impl SourceLine
+{
+    fn error(&self, message : String) -> AssembleError
+    {
+        AssembleError { file : self.file.clone(), line : self.line, message }
+    }
+}
+
+#[allow(dead_code)]
+pub fn assemble_file(path : &Path) -> Result<Vec<u8>, AssembleError>
+{
+    let file_name = path.to_string_lossy().into_owned();
+    let source = read_source(path).map_err(|message| AssembleError { file : file_name.clone(), line : 0, message })?;
+    let directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+    let mut lines = Vec::new();
+    parse_source(&source, &file_name, &directory, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+//include directives are resolved relative to include_directory
+#[allow(dead_code)]
+pub fn assemble(source : &str, include_directory : &Path) -> Result<Vec<u8>, AssembleError>
+{
+    let mut lines = Vec::new();
+    parse_source(source, "<source>", include_directory, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+fn read_source(path : &Path) -> Result<String, String>
+{
+    let mut source = String::new();
+    let mut file = File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+    file.read_to_string(&mut source).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    Ok(source)
+}
+
+fn parse_source(source : &str, file_name : &str, directory : &Path, depth : usize, lines : &mut Vec<SourceLine>) -> Result<(), AssembleError>
+{
+    for (index, raw_line) in source.lines().enumerate()
+    {
+        let line_number = index + 1;
+        let error = |message : String| AssembleError { file : file_name.to_string(), line : line_number, message };
+
+        let mut text = match raw_line.find(';')
+        {
+            Some(position) => &raw_line[.. position],
+            None => raw_line,
+        }.trim();
+
+        let mut label = None;
+        if let Some(position) = text.find(':')
+        {
+            let name = text[.. position].trim();
+            if !is_identifier(name)
+            {
+                return Err(error(format!("invalid label name '{}'", name)));
+            }
+            label = Some(name.to_string());
+            text = text[position + 1 ..].trim();
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace)
+        {
+            Some(position) => (&text[.. position], text[position ..].trim()),
+            None => (text, ""),
+        };
+
+        if mnemonic.eq_ignore_ascii_case("include")
+        {
+            if depth >= MAX_INCLUDE_DEPTH
+            {
+                return Err(error(format!("includes nested deeper than {} levels", MAX_INCLUDE_DEPTH)));
+            }
+            let included = rest.trim_matches('"');
+            let path = directory.join(included);
+            let included_source = read_source(&path).map_err(&error)?;
+            let included_directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            if label.is_some()
+            {
+                lines.push(SourceLine { file : file_name.to_string(), line : line_number, label, mnemonic : String::new(), operands : Vec::new() });
+            }
+            parse_source(&included_source, &path.to_string_lossy(), &included_directory, depth + 1, lines)?;
+            continue;
+        }
+
+        //NAME EQU value
+        let words : Vec<&str> = text.splitn(3, char::is_whitespace).filter(|w| !w.is_empty()).collect();
+        if words.len() >= 2 && words[1].eq_ignore_ascii_case("equ")
+        {
+            if !is_identifier(words[0])
+            {
+                return Err(error(format!("invalid constant name '{}'", words[0])));
+            }
+            let value = text[words[0].len() ..].trim()[3 ..].trim();
+            lines.push(SourceLine { file : file_name.to_string(), line : line_number, label, mnemonic : "EQU".to_string(), operands : vec![words[0].to_string(), value.to_string()] });
+            continue;
+        }
+
+        let operands = if rest.is_empty()
+        {
+            Vec::new()
+        }
+        else
+        {
+            rest.split(',').map(|operand| operand.trim().to_string()).collect()
+        };
+
+        if label.is_some() || !mnemonic.is_empty()
+        {
+            lines.push(SourceLine { file : file_name.to_string(), line : line_number, label, mnemonic : mnemonic.to_uppercase(), operands });
+        }
+    }
+    Ok(())
+}
+
+enum Symbol
+{
+    Label(u32),
+    Constant(String),
+}
+
+struct SymbolTable
+{
+    symbols : HashMap<String, Symbol>,
+}
+
+impl SymbolTable
+{
+    fn evaluate(&self, expression : &str, depth : usize) -> Result<i64, String>
+    {
+        if depth > MAX_CONSTANT_DEPTH
+        {
+            return Err(format!("constant definitions are recursive in '{}'", expression));
+        }
+
+        let expression = expression.trim();
+        if expression.is_empty()
+        {
+            return Err("missing value".to_string());
+        }
+
+        let mut total : i64 = 0;
+        let mut sign : i64 = 1;
+        let mut term = String::new();
+        for c in expression.chars().chain(Some('+'))
+        {
+            if c == '+' || c == '-'
+            {
+                //Unary sign
+                if term.trim().is_empty()
+                {
+                    if c == '-'
+                    {
+                        sign = -sign;
+                    }
+                    continue;
+                }
+                total = total.checked_add(sign * self.evaluate_term(term.trim(), depth)?).ok_or_else(|| format!("'{}' is out of range", expression))?;
+                term.clear();
+                sign = if c == '-' { -1 } else { 1 };
+            }
+            else
+            {
+                term.push(c);
+            }
+        }
+        Ok(total)
+    }
+
+    fn evaluate_term(&self, term : &str, depth : usize) -> Result<i64, String>
+    {
+        if let Some(value) = parse_number(term)
+        {
+            return Ok(value);
+        }
+        match self.symbols.get(term)
+        {
+            Some(&Symbol::Label(address)) => Ok(address as i64),
+            Some(Symbol::Constant(expression)) => self.evaluate(expression, depth + 1),
+            None => Err(format!("unknown value '{}'", term)),
+        }
+    }
+
+    fn value(&self, line : &SourceLine, expression : &str, max : i64) -> Result<u16, AssembleError>
+    {
+        let value = self.evaluate(expression, 0).map_err(|message| line.error(message))?;
+        //Negative bytes are accepted as two's complement
+        if max == 0xFF && (-128..0).contains(&value)
+        {
+            return Ok((value + 256) as u16);
+        }
+        if value < 0 || value > max
+        {
+            return Err(line.error(format!("value {} of '{}' does not fit in the range 0-{}", value, expression, max)));
+        }
+        Ok(value as u16)
+    }
+}
+
+fn assemble_lines(lines : &[SourceLine]) -> Result<Vec<u8>, AssembleError>
+{
+    //First pass: give an address to every label
+    let mut symbols = SymbolTable { symbols : HashMap::new() };
+    let mut address = ROM_BASE_ADDRESS;
+    for line in lines
+    {
+        if let Some(ref label) = line.label
+        {
+            define_symbol(&mut symbols, line, label, Symbol::Label(address))?;
+        }
+        if line.mnemonic == "EQU"
+        {
+            define_symbol(&mut symbols, line, &line.operands[0], Symbol::Constant(line.operands[1].clone()))?;
+            continue;
+        }
+        address += size_of(line);
+    }
+
+    //Second pass: encode
+    let mut rom = Vec::new();
+    for line in lines
+    {
+        match line.mnemonic.as_str()
+        {
+            "" | "EQU" => {},
+            "DB" =>
+            {
+                expect_operands_at_least(line, 1)?;
+                for operand in &line.operands
+                {
+                    rom.push(symbols.value(line, operand, 0xFF)? as u8);
+                }
+            }
+            "DW" =>
+            {
+                expect_operands_at_least(line, 1)?;
+                for operand in &line.operands
+                {
+                    let word = symbols.value(line, operand, 0xFFFF)?;
+                    rom.push((word >> 8) as u8);
+                    rom.push(word as u8);
+                }
+            }
+            _ =>
+            {
+                let (instruction, long_address) = encode_instruction(&symbols, line)?;
+                let opcode = instruction.encode();
+                rom.push((opcode >> 8) as u8);
+                rom.push(opcode as u8);
+                if let Some(word) = long_address
+                {
+                    rom.push((word >> 8) as u8);
+                    rom.push(word as u8);
+                }
+            }
+        }
+    }
+    Ok(rom)
+}
+
+fn define_symbol(symbols : &mut SymbolTable, line : &SourceLine, name : &str, symbol : Symbol) -> Result<(), AssembleError>
+{
+    if symbols.symbols.contains_key(name)
+    {
+        return Err(line.error(format!("'{}' is defined more than once", name)));
+    }
+    symbols.symbols.insert(name.to_string(), symbol);
+    Ok(())
+}
+
+fn size_of(line : &SourceLine) -> u32
+{
+    match line.mnemonic.as_str()
+    {
+        "" | "EQU" => 0,
+        "DB" => line.operands.len() as u32,
+        "DW" => 2 * line.operands.len() as u32,
+        "LD" if line.operands.len() == 2 && is_long_operand(&line.operands[1]) => 4,
+        _ => 2,
+    }
+}
+
+fn is_long_operand(operand : &str) -> bool
+{
+    match operand.get(.. 4)
+    {
+        Some(prefix) => prefix.eq_ignore_ascii_case("long") && operand[4 ..].starts_with(char::is_whitespace),
+        None => false,
+    }
+}
+
+fn expect_operands(line : &SourceLine, count : usize) -> Result<(), AssembleError>
+{
+    if line.operands.len() != count
+    {
+        return Err(line.error(format!("{} expects {} operands but got {}", line.mnemonic, count, line.operands.len())));
+    }
+    Ok(())
+}
+
+fn expect_operands_at_least(line : &SourceLine, count : usize) -> Result<(), AssembleError>
+{
+    if line.operands.len() < count
+    {
+        return Err(line.error(format!("{} expects at least {} operands", line.mnemonic, count)));
+    }
+    Ok(())
+}
+
+fn register(line : &SourceLine, operand : &str) -> Result<u8, AssembleError>
+{
+    parse_register(operand).ok_or_else(|| line.error(format!("expected a register V0-VF but got '{}'", operand)))
+}
+
+fn encode_instruction(symbols : &SymbolTable, line : &SourceLine) -> Result<(Instruction, Option<u16>), AssembleError>
+{
+    let operands : Vec<&str> = line.operands.iter().map(|o| o.as_str()).collect();
+    let upper : Vec<String> = operands.iter().map(|o| o.to_uppercase()).collect();
+    let address = |operand : &str| symbols.value(line, operand, 0xFFF);
+    let byte = |operand : &str| symbols.value(line, operand, 0xFF).map(|v| v as u8);
+    let nibble = |operand : &str| symbols.value(line, operand, 0xF).map(|v| v as u8);
+
+    let instruction = match line.mnemonic.as_str()
+    {
+        "CLS" => { expect_operands(line, 0)?; Instruction::Cls }
+        "RET" => { expect_operands(line, 0)?; Instruction::Ret }
+        "SCR" => { expect_operands(line, 0)?; Instruction::ScrollRight }
+        "SCL" => { expect_operands(line, 0)?; Instruction::ScrollLeft }
+        "EXIT" => { expect_operands(line, 0)?; Instruction::Exit }
+        "LOW" => { expect_operands(line, 0)?; Instruction::LowResolution }
+        "HIGH" => { expect_operands(line, 0)?; Instruction::HighResolution }
+        "AUDIO" => { expect_operands(line, 0)?; Instruction::Audio }
+        "SYS" => { expect_operands(line, 1)?; Instruction::Sys(address(operands[0])?) }
+        "SCD" => { expect_operands(line, 1)?; Instruction::ScrollDown(nibble(operands[0])?) }
+        "CALL" => { expect_operands(line, 1)?; Instruction::Call(address(operands[0])?) }
+        "PLANE" => { expect_operands(line, 1)?; Instruction::Plane(nibble(operands[0])?) }
+        "SKP" => { expect_operands(line, 1)?; Instruction::Skp(register(line, operands[0])?) }
+        "SKNP" => { expect_operands(line, 1)?; Instruction::Sknp(register(line, operands[0])?) }
+        "PITCH" => { expect_operands(line, 1)?; Instruction::Pitch(register(line, operands[0])?) }
+        "JP" if operands.len() == 2 =>
+        {
+            if upper[0] != "V0"
+            {
+                return Err(line.error("JP with two operands only accepts V0 as offset register".to_string()));
+            }
+            Instruction::JpOffset(address(operands[1])?)
+        }
+        "JP" => { expect_operands(line, 1)?; Instruction::Jp(address(operands[0])?) }
+        "ADD" if operands.len() == 2 && upper[0] == "I" =>
+        {
+            Instruction::AddI(register(line, operands[1])?)
+        }
+        "SE" | "SNE" | "ADD" =>
+        {
+            expect_operands(line, 2)?;
+            let x = register(line, operands[0])?;
+            match (parse_register(operands[1]), line.mnemonic.as_str())
+            {
+                (Some(y), "SE") => Instruction::SeRegister { x, y },
+                (Some(y), "SNE") => Instruction::SneRegister { x, y },
+                (Some(y), _) => Instruction::AddRegister { x, y },
+                (None, "SE") => Instruction::SeByte { x, byte : byte(operands[1])? },
+                (None, "SNE") => Instruction::SneByte { x, byte : byte(operands[1])? },
+                (None, _) => Instruction::AddByte { x, byte : byte(operands[1])? },
+            }
+        }
+        "SAVE" | "LOAD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" =>
+        {
+            expect_operands(line, 2)?;
+            let x = register(line, operands[0])?;
+            let y = register(line, operands[1])?;
+            match line.mnemonic.as_str()
+            {
+                "SAVE" => Instruction::SaveRange { x, y },
+                "LOAD" => Instruction::LoadRange { x, y },
+                "OR" => Instruction::Or { x, y },
+                "AND" => Instruction::And { x, y },
+                "XOR" => Instruction::Xor { x, y },
+                "SUB" => Instruction::Sub { x, y },
+                _ => Instruction::Subn { x, y },
+            }
+        }
+        //The second register is optional, it defaults to the shifted one
+        "SHR" | "SHL" =>
+        {
+            if operands.len() != 1
+            {
+                expect_operands(line, 2)?;
+            }
+            let x = register(line, operands[0])?;
+            let y = if operands.len() == 2 { register(line, operands[1])? } else { x };
+            if line.mnemonic == "SHR" { Instruction::Shr { x, y } } else { Instruction::Shl { x, y } }
+        }
+        "RND" =>
+        {
+            expect_operands(line, 2)?;
+            Instruction::Rnd { x : register(line, operands[0])?, byte : byte(operands[1])? }
+        }
+        "DRW" =>
+        {
+            expect_operands(line, 3)?;
+            Instruction::Drw { x : register(line, operands[0])?, y : register(line, operands[1])?, n : nibble(operands[2])? }
+        }
+        "LD" =>
+        {
+            expect_operands(line, 2)?;
+            match (upper[0].as_str(), upper[1].as_str())
+            {
+                ("I", _) if is_long_operand(operands[1]) =>
+                {
+                    let long_address = symbols.value(line, &operands[1][4 ..], 0xFFFF)?;
+                    return Ok((Instruction::LdILong, Some(long_address)));
+                }
+                ("I", _) => Instruction::LdI(address(operands[1])?),
+                ("DT", _) => Instruction::LdDelayTimer(register(line, operands[1])?),
+                ("ST", _) => Instruction::LdSoundTimer(register(line, operands[1])?),
+                ("F", _) => Instruction::LdFont(register(line, operands[1])?),
+                ("HF", _) => Instruction::LdBigFont(register(line, operands[1])?),
+                ("B", _) => Instruction::Bcd(register(line, operands[1])?),
+                ("[I]", _) => Instruction::Store(register(line, operands[1])?),
+                ("R", _) => Instruction::StoreFlags(register(line, operands[1])?),
+                (_, "DT") => Instruction::LdFromDelayTimer(register(line, operands[0])?),
+                (_, "K") => Instruction::WaitKey(register(line, operands[0])?),
+                (_, "[I]") => Instruction::Load(register(line, operands[0])?),
+                (_, "R") => Instruction::LoadFlags(register(line, operands[0])?),
+                _ =>
+                {
+                    let x = register(line, operands[0])?;
+                    match parse_register(operands[1])
+                    {
+                        Some(y) => Instruction::LdRegister { x, y },
+                        None => Instruction::LdByte { x, byte : byte(operands[1])? },
+                    }
+                }
+            }
+        }
+        _ => return Err(line.error(format!("unknown instruction '{}'", line.mnemonic))),
+    };
+    Ok((instruction, None))
+}
+
+fn parse_register(operand : &str) -> Option<u8>
+{
+    let operand = operand.trim();
+    if operand.len() == 2 && (operand.starts_with('V') || operand.starts_with('v'))
+    {
+        u8::from_str_radix(&operand[1 ..], 16).ok()
+    }
+    else
+    {
+        None
+    }
+}
+
+fn parse_number(text : &str) -> Option<i64>
+{
+    let lower = text.to_lowercase();
+    if let Some(digits) = lower.strip_prefix("0x")
+    {
+        i64::from_str_radix(digits, 16).ok()
+    }
+    else if let Some(digits) = lower.strip_prefix('#')
+    {
+        i64::from_str_radix(digits, 16).ok()
+    }
+    else if let Some(digits) = lower.strip_prefix("0b")
+    {
+        i64::from_str_radix(digits, 2).ok()
+    }
+    else if lower.starts_with(|c : char| c.is_ascii_digit())
+    {
+        lower.parse::<i64>().ok()
+    }
+    else
+    {
+        None
+    }
+}
+
+fn is_identifier(name : &str) -> bool
+{
+    !name.is_empty()
+        && name.starts_with(|c : char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use disassembler::disassemble;
+
+    fn assemble_source(source : &str) -> Result<Vec<u8>, AssembleError>
+    {
+        assemble(source, Path::new("."))
+    }
+
+    fn error_line(source : &str) -> usize
+    {
+        assemble_source(source).unwrap_err().line
+    }
+
+    #[test]
+    fn every_mnemonic()
+    {
+        let cases : &[(&str, &[u8])] = &[
+            ("CLS", &[0x00, 0xE0]),
+            ("RET", &[0x00, 0xEE]),
+            ("SYS 0x123", &[0x01, 0x23]),
+            ("SCD 5", &[0x00, 0xC5]),
+            ("SCR", &[0x00, 0xFB]),
+            ("SCL", &[0x00, 0xFC]),
+            ("EXIT", &[0x00, 0xFD]),
+            ("LOW", &[0x00, 0xFE]),
+            ("HIGH", &[0x00, 0xFF]),
+            ("JP 0x234", &[0x12, 0x34]),
+            ("CALL 0x345", &[0x23, 0x45]),
+            ("SE VA, 0x12", &[0x3A, 0x12]),
+            ("SNE VB, 0x34", &[0x4B, 0x34]),
+            ("SE V1, V2", &[0x51, 0x20]),
+            ("SAVE V3, V4", &[0x53, 0x42]),
+            ("LOAD V5, V6", &[0x55, 0x63]),
+            ("LD VC, 0x56", &[0x6C, 0x56]),
+            ("ADD VD, 0x78", &[0x7D, 0x78]),
+            ("LD V1, V2", &[0x81, 0x20]),
+            ("OR V2, V3", &[0x82, 0x31]),
+            ("AND V3, V4", &[0x83, 0x42]),
+            ("XOR V4, V5", &[0x84, 0x53]),
+            ("ADD V5, V6", &[0x85, 0x64]),
+            ("SUB V6, V7", &[0x86, 0x75]),
+            ("SHR V7, V8", &[0x87, 0x86]),
+            ("SHR V7", &[0x87, 0x76]),
+            ("SUBN V8, V9", &[0x88, 0x97]),
+            ("SHL V9, VA", &[0x89, 0xAE]),
+            ("SNE VE, VF", &[0x9E, 0xF0]),
+            ("LD I, 0x456", &[0xA4, 0x56]),
+            ("JP V0, 0x567", &[0xB5, 0x67]),
+            ("RND VE, 0x9A", &[0xCE, 0x9A]),
+            ("DRW V1, V2, 15", &[0xD1, 0x2F]),
+            ("SKP V3", &[0xE3, 0x9E]),
+            ("SKNP V4", &[0xE4, 0xA1]),
+            ("LD I, LONG 0x1234", &[0xF0, 0x00, 0x12, 0x34]),
+            ("PLANE 2", &[0xF2, 0x01]),
+            ("AUDIO", &[0xF0, 0x02]),
+            ("LD V5, DT", &[0xF5, 0x07]),
+            ("LD V6, K", &[0xF6, 0x0A]),
+            ("LD DT, V7", &[0xF7, 0x15]),
+            ("LD ST, V8", &[0xF8, 0x18]),
+            ("ADD I, V9", &[0xF9, 0x1E]),
+            ("LD F, VA", &[0xFA, 0x29]),
+            ("LD HF, VB", &[0xFB, 0x30]),
+            ("LD B, VC", &[0xFC, 0x33]),
+            ("PITCH VD", &[0xFD, 0x3A]),
+            ("LD [I], VE", &[0xFE, 0x55]),
+            ("LD VF, [I]", &[0xFF, 0x65]),
+            ("LD R, V1", &[0xF1, 0x75]),
+            ("LD V2, R", &[0xF2, 0x85]),
+            ("DB 0x80, 0b101, 7, -1", &[0x80, 0x05, 0x07, 0xFF]),
+            ("DW #1234, 0x5678", &[0x12, 0x34, 0x56, 0x78]),
+            ("ld v1, v2", &[0x81, 0x20]),
+        ];
+        for &(source, bytes) in cases
+        {
+            assert_eq!(assemble_source(source), Ok(bytes.to_vec()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn labels_and_constants()
+    {
+        let source = "SPEED EQU 3\nstart: LD V0, SPEED + 1\n    LD I, ball\n    JP start\nball:\n    DB ball - start";
+        assert_eq!(assemble_source(source), Ok(vec![0x60, 0x04, 0xA2, 0x06, 0x12, 0x00, 0x06]));
+    }
+
+    #[test]
+    fn errors_report_their_line()
+    {
+        assert_eq!(error_line("CLS\nFOO V1"), 2);
+        assert_eq!(error_line("CLS\nCLS\nLD VG, 1"), 3);
+        assert_eq!(error_line("LD V1, 0x100"), 1);
+        assert_eq!(error_line("CLS\nJP nowhere"), 2);
+        assert_eq!(error_line("a:\nCLS\na: CLS"), 3);
+        assert_eq!(error_line("CLS\nDRW V1, V2"), 2);
+        assert_eq!(error_line("JP V1, 0x200"), 1);
+        assert_eq!(error_line("A EQU B\nB EQU A\nLD V0, A"), 3);
+        assert_eq!(error_line("CLS\n1abel: CLS"), 2);
+        assert_eq!(error_line("DB 0x7FFFFFFFFFFFFFFF + 1"), 1);
+    }
+
+    #[test]
+    fn multi_byte_characters_are_errors()
+    {
+        assert!(assemble_source("LD I, aéé").is_err());
+        assert!(assemble_source("LD I, é").is_err());
+        assert!(assemble_source("LD Vé, 1").is_err());
+    }
+
+    #[test]
+    fn disassembled_opcodes_reassemble()
+    {
+        for opcode in 0 ..= 0xFFFFu32
+        {
+            let rom = [(opcode >> 8) as u8, opcode as u8];
+            let source = disassemble(&rom);
+            assert_eq!(assemble_source(&source), Ok(rom.to_vec()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn disassembled_program_reassembles()
+    {
+        let source = "start: CLS\n    LD I, LONG sprite\n    CALL draw\n    SE V0, 1\n    JP start\n    EXIT\ndraw: DRW V0, V1, 2\n    RET\nsprite: DB 0x3C, 0x42, 0x42";
+        let rom = assemble_source(source).unwrap();
+        assert_eq!(assemble_source(&disassemble(&rom)), Ok(rom));
+    }
+}
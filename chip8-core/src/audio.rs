@@ -0,0 +1,473 @@
+/*
+Beeper for the sound timer. The interpreter only exposes whether the tone should play
+and the XO-CHIP audio pattern, the frontend asks the Beeper for one frame of samples after
+every emulated frame and they are sent to an AudioOutput: the sound card, a WAV file or
+nowhere. The sound card output needs the audio feature.
+*/
+
+#[cfg(feature = "audio")]
+extern crate cpal;
+
+#[cfg(feature = "audio")]
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+#[cfg(feature = "audio")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "audio")]
+use std::thread;
+use chip8::Chip8;
+use clock::FRAMES_PER_SECOND;
+
+pub const DEFAULT_SAMPLE_RATE : u32 = 44100;
+
+pub trait AudioOutput
+{
+    fn sample_rate(&self) -> u32;
+    //Mono samples between -1 and 1
+    fn write(&mut self, samples : &[f32]) -> io::Result<()>;
+}
+
+//Discards everything, for headless runs
+pub struct NullOutput
+{
+    sample_rate : u32,
+}
+
+impl NullOutput
+{
+    #[allow(dead_code)]
+    pub fn new(sample_rate : u32) -> NullOutput
+    {
+        NullOutput { sample_rate }
+    }
+}
+
+impl AudioOutput for NullOutput
+{
+    fn sample_rate(&self) -> u32
+    {
+        self.sample_rate
+    }
+
+    fn write(&mut self, _ : &[f32]) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+//16 bits mono PCM, the sizes in the header are filled in when the output is dropped
+pub struct WavOutput
+{
+    file : File,
+    sample_rate : u32,
+    data_size : u32,
+}
+
+impl WavOutput
+{
+    #[allow(dead_code)]
+    pub fn create(path : &Path, sample_rate : u32) -> io::Result<WavOutput>
+    {
+        let mut output = WavOutput { file : File::create(path)?, sample_rate, data_size : 0 };
+        output.write_header()?;
+        Ok(output)
+    }
+
+    fn write_header(&mut self) -> io::Result<()>
+    {
+        let channels : u16 = 1;
+        let bits_per_sample : u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+        let byte_rate = self.sample_rate * block_align as u32;
+
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&u32_to_le(36 + self.data_size));
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&u32_to_le(16));
+        header.extend_from_slice(&u16_to_le(1));
+        header.extend_from_slice(&u16_to_le(channels));
+        header.extend_from_slice(&u32_to_le(self.sample_rate));
+        header.extend_from_slice(&u32_to_le(byte_rate));
+        header.extend_from_slice(&u16_to_le(block_align));
+        header.extend_from_slice(&u16_to_le(bits_per_sample));
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&u32_to_le(self.data_size));
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioOutput for WavOutput
+{
+    fn sample_rate(&self) -> u32
+    {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples : &[f32]) -> io::Result<()>
+    {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples
+        {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            data.extend_from_slice(&u16_to_le(value as u16));
+        }
+        self.file.write_all(&data)?;
+        self.data_size += data.len() as u32;
+        Ok(())
+    }
+}
+
+impl Drop for WavOutput
+{
+    fn drop(&mut self)
+    {
+        let _ = self.write_header();
+    }
+}
+
+//Default sound card, fed from a queue that the audio thread drains
+#[cfg(feature = "audio")]
+pub struct DeviceOutput
+{
+    queue : Arc<Mutex<VecDeque<f32>>>,
+    sample_rate : u32,
+}
+
+//Samples beyond that are dropped so that fast-forwarding does not build up latency
+#[cfg(feature = "audio")]
+const MAX_QUEUED_SECONDS : f64 = 0.1;
+
+#[cfg(feature = "audio")]
+impl DeviceOutput
+{
+    #[allow(dead_code)]
+    pub fn open() -> Result<DeviceOutput, String>
+    {
+        let device = cpal::default_output_device().ok_or("no audio output device".to_string())?;
+        let format = device.default_output_format().map_err(|e| e.to_string())?;
+        let event_loop = cpal::EventLoop::new();
+        let stream_id = event_loop.build_output_stream(&device, &format).map_err(|e| e.to_string())?;
+        event_loop.play_stream(stream_id);
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let audio_queue = queue.clone();
+        let channels = format.channels as usize;
+        thread::spawn(move ||
+        {
+            event_loop.run(move |_, data|
+            {
+                let mut queue = audio_queue.lock().unwrap();
+                if let cpal::StreamData::Output { buffer } = data
+                {
+                    match buffer
+                    {
+                        cpal::UnknownTypeOutputBuffer::F32(mut buffer) =>
+                        {
+                            for frame in buffer.chunks_mut(channels)
+                            {
+                                let sample = queue.pop_front().unwrap_or(0.0);
+                                for out in frame.iter_mut() { *out = sample; }
+                            }
+                        }
+                        cpal::UnknownTypeOutputBuffer::I16(mut buffer) =>
+                        {
+                            for frame in buffer.chunks_mut(channels)
+                            {
+                                let sample = queue.pop_front().unwrap_or(0.0);
+                                for out in frame.iter_mut() { *out = (sample * i16::MAX as f32) as i16; }
+                            }
+                        }
+                        cpal::UnknownTypeOutputBuffer::U16(mut buffer) =>
+                        {
+                            for frame in buffer.chunks_mut(channels)
+                            {
+                                let sample = queue.pop_front().unwrap_or(0.0);
+                                for out in frame.iter_mut() { *out = ((sample + 1.0) * 0.5 * u16::MAX as f32) as u16; }
+                            }
+                        }
+                    }
+                }
+            });
+        });
+
+        Ok(DeviceOutput { queue, sample_rate : format.sample_rate.0 })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioOutput for DeviceOutput
+{
+    fn sample_rate(&self) -> u32
+    {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples : &[f32]) -> io::Result<()>
+    {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples.iter().cloned());
+        let max_samples = (self.sample_rate as f64 * MAX_QUEUED_SECONDS) as usize;
+        while queue.len() > max_samples
+        {
+            queue.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SquareWave
+{
+    pub frequency : f64,
+    //between 0 and 1
+    pub volume : f32,
+    phase : f64,
+}
+
+impl SquareWave
+{
+    #[allow(dead_code)]
+    pub fn new(frequency : f64, volume : f32) -> SquareWave
+    {
+        SquareWave { frequency, volume, phase : 0.0 }
+    }
+
+    //The phase keeps going across calls so that consecutive frames join without clicks
+    pub fn generate(&mut self, sample_rate : u32, count : usize, samples : &mut Vec<f32>)
+    {
+        let step = self.frequency / sample_rate as f64;
+        for _ in 0 .. count
+        {
+            samples.push(if self.phase < 0.5 { self.volume } else { -self.volume });
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+impl Default for SquareWave
+{
+    fn default() -> SquareWave
+    {
+        SquareWave::new(440.0, 0.25)
+    }
+}
+
+pub struct Beeper
+{
+    pub tone : SquareWave,
+    output : Box<dyn AudioOutput>,
+    pending_samples : f64,
+    samples : Vec<f32>,
+    //Bit of the XO-CHIP audio pattern being played, with the fraction of it already played
+    pattern_position : f64,
+}
+
+impl Beeper
+{
+    #[allow(dead_code)]
+    pub fn new(tone : SquareWave, output : Box<dyn AudioOutput>) -> Beeper
+    {
+        Beeper { tone, output, pending_samples : 0.0, samples : Vec::new(), pattern_position : 0.0 }
+    }
+
+    //For example to go on silently after the output failed
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output : Box<dyn AudioOutput>)
+    {
+        self.output = output;
+        self.pending_samples = 0.0;
+    }
+
+    //Outputs one 60Hz frame of tone or silence
+    #[allow(dead_code)]
+    pub fn play_frame(&mut self, sound_on : bool) -> io::Result<()>
+    {
+        let count = self.samples_in_frame();
+        self.samples.clear();
+        if sound_on
+        {
+            self.tone.generate(self.output.sample_rate(), count, &mut self.samples);
+        }
+        else
+        {
+            self.samples.resize(count, 0.0);
+        }
+        self.output.write(&self.samples)
+    }
+
+    /*
+    Outputs one 60Hz frame of the sound of the interpreter. Once a program loaded an XO-CHIP
+    audio pattern with F002 its 128 bits are looped at the rate set by FX3A, before that the
+    pattern is all zeros and the usual tone plays instead.
+    */
+    #[allow(dead_code)]
+    pub fn play_chip8_frame(&mut self, chip8 : &Chip8) -> io::Result<()>
+    {
+        let pattern = chip8.audio_pattern();
+        if !chip8.is_sound_playing() || pattern.iter().all(|&byte| byte == 0)
+        {
+            return self.play_frame(chip8.is_sound_playing());
+        }
+
+        let count = self.samples_in_frame();
+        let step = chip8.audio_playback_rate() / self.output.sample_rate() as f64;
+        let bits = (pattern.len() * 8) as f64;
+        self.samples.clear();
+        for _ in 0 .. count
+        {
+            let bit = self.pattern_position as usize;
+            let is_high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            self.samples.push(if is_high { self.tone.volume } else { -self.tone.volume });
+            self.pattern_position = (self.pattern_position + step) % bits;
+        }
+        self.output.write(&self.samples)
+    }
+
+    //Frames do not hold a whole number of samples, the remainders add up over time
+    fn samples_in_frame(&mut self) -> usize
+    {
+        self.pending_samples += self.output.sample_rate() as f64 / FRAMES_PER_SECOND;
+        let count = self.pending_samples.floor();
+        self.pending_samples -= count;
+        count as usize
+    }
+}
+
+fn u16_to_le(value : u16) -> [u8; 2]
+{
+    [value as u8, (value >> 8) as u8]
+}
+
+fn u32_to_le(value : u32) -> [u8; 4]
+{
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::rc::Rc;
+    use quirks::Quirks;
+    use random::RandomGenerator;
+
+    struct Collector
+    {
+        sample_rate : u32,
+        frames : Rc<RefCell<Vec<Vec<f32>>>>,
+    }
+
+    impl AudioOutput for Collector
+    {
+        fn sample_rate(&self) -> u32
+        {
+            self.sample_rate
+        }
+
+        fn write(&mut self, samples : &[f32]) -> io::Result<()>
+        {
+            self.frames.borrow_mut().push(samples.to_vec());
+            Ok(())
+        }
+    }
+
+    fn new_beeper(tone : SquareWave, sample_rate : u32) -> (Beeper, Rc<RefCell<Vec<Vec<f32>>>>)
+    {
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        (Beeper::new(tone, Box::new(Collector { sample_rate, frames : frames.clone() })), frames)
+    }
+
+    #[test]
+    fn wav_files_have_their_sizes_filled_in()
+    {
+        let path = env::temp_dir().join("audio_wav_sizes.wav");
+        {
+            let mut output = WavOutput::create(&path, 8000).unwrap();
+            output.write(&[0.0, 1.0]).unwrap();
+            output.write(&[-1.0, 2.0]).unwrap();
+        }
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0 .. 4], b"RIFF");
+        assert_eq!(&data[4 .. 8], [44, 0, 0, 0]);
+        assert_eq!(&data[8 .. 16], b"WAVEfmt ");
+        //PCM, mono, 8000Hz, 16000 bytes per second, 2 bytes per sample, 16 bits
+        assert_eq!(&data[16 .. 36], [16, 0, 0, 0, 1, 0, 1, 0, 0x40, 0x1F, 0, 0, 0x80, 0x3E, 0, 0, 2, 0, 16, 0]);
+        assert_eq!(&data[36 .. 44], [b'd', b'a', b't', b'a', 8, 0, 0, 0]);
+        //Out of range samples are clipped
+        assert_eq!(&data[44 ..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn frames_add_up_to_the_sample_rate()
+    {
+        let (mut beeper, frames) = new_beeper(SquareWave::default(), 8000);
+        for _ in 0 .. 60
+        {
+            beeper.play_frame(false).unwrap();
+        }
+        let frames = frames.borrow();
+        let lengths : Vec<usize> = frames.iter().map(|frame| frame.len()).collect();
+        assert_eq!(&lengths[.. 3], [133, 133, 134]);
+        assert_eq!(lengths.iter().sum::<usize>(), 8000);
+        assert!(frames.iter().all(|frame| frame.iter().all(|&sample| sample == 0.0)));
+    }
+
+    #[test]
+    fn square_waves_alternate_between_the_volume_levels()
+    {
+        let mut tone = SquareWave::new(2000.0, 0.5);
+        let mut samples = Vec::new();
+        tone.generate(8000, 3, &mut samples);
+        //The phase carries over to the next call
+        tone.generate(8000, 5, &mut samples);
+        assert_eq!(samples, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+
+        let (mut beeper, frames) = new_beeper(SquareWave::new(2000.0, 0.5), 8000);
+        beeper.play_frame(true).unwrap();
+        let frames = frames.borrow();
+        assert_eq!(frames[0].len(), 133);
+        assert_eq!(&frames[0][.. 6], [0.5, 0.5, -0.5, -0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn xo_chip_patterns_are_played_at_the_pitch_rate()
+    {
+        //LD I, 0x206 / AUDIO / JP 0x204, then a pattern of 4 high bits and 4 low bits
+        let mut rom = vec![0xA2, 0x06, 0xF0, 0x02, 0x12, 0x04];
+        rom.extend_from_slice(&[0xF0; 16]);
+        let mut chip8 = Chip8::new(&rom, Quirks::xo_chip(), RandomGenerator::seeded(0)).unwrap();
+        chip8.run_one_cycle().unwrap();
+        chip8.run_one_cycle().unwrap();
+        assert_eq!(chip8.audio_playback_rate(), 4000.0);
+
+        let (mut beeper, frames) = new_beeper(SquareWave::new(440.0, 0.5), 8000);
+        //Silent without the sound timer
+        beeper.play_chip8_frame(&chip8).unwrap();
+        chip8.set_sound_timer(2);
+        beeper.play_chip8_frame(&chip8).unwrap();
+        beeper.play_chip8_frame(&chip8).unwrap();
+        let frames = frames.borrow();
+        assert!(frames[0].iter().all(|&sample| sample == 0.0));
+        //Two samples per bit at 8000Hz, the position carries over to the next frame
+        let samples : Vec<f32> = frames[1].iter().chain(frames[2].iter()).cloned().collect();
+        for (i, sample) in samples.iter().enumerate()
+        {
+            assert_eq!(*sample, if i % 16 < 8 { 0.5 } else { -0.5 }, "sample {}", i);
+        }
+    }
+}
@@ -0,0 +1,1786 @@
+use std::mem;
+use quirks::{Quirks, LoadStoreQuirk, MIN_MEMORY_SIZE, MAX_MEMORY_SIZE};
+use error::{Chip8Error, UnknownOpcodePolicy};
+use instruction::Instruction;
+use random::RandomGenerator;
+use savestate::{self, StateWriter, StateReader, SaveStateError};
+use trace::{Tracer, TraceEntry};
+
+//The stack holds the addresses of the 2NNN instructions, which have to be in memory
+fn is_call_address(address : u16, memory_size : usize) -> bool
+{
+    address as usize + 1 < memory_size
+}
+
+//Where the return address of the call at the given depth is kept when the stack is in memory
+fn memory_stack_address(depth : usize) -> usize
+{
+    MEMORY_STACK_TOP.wrapping_sub(2 * (depth + 1))
+}
+
+//Registers affected by 5XY2/5XY3, which also accept X > Y to go in reverse order
+fn register_range(x : usize, y : usize) -> Vec<usize>
+{
+    if x <= y
+    {
+        (x .. y + 1).collect()
+    }
+    else
+    {
+        (y .. x + 1).rev().collect()
+    }
+}
+
+#[allow(dead_code)]
+fn create_font_data() -> Vec<u8>
+{
+    let mut data : Vec<u8> = Vec::with_capacity(5*16);
+
+    //0
+    data.extend_from_slice(&[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+
+    //1
+    data.extend_from_slice(&[0x20, 0x60, 0x20, 0x20, 0x70]);
+
+    //2
+    data.extend_from_slice(&[0xF0, 0x10, 0xF0, 0x80, 0xF0]);
+
+    //3
+    data.extend_from_slice(&[0xF0, 0x10, 0xF0, 0x10, 0xF0]);
+
+    //4
+    data.extend_from_slice(&[0x90, 0x90, 0xF0, 0x10, 0x10]);
+
+    //5
+    data.extend_from_slice(&[0xF0, 0x80, 0xF0, 0x10, 0xF0]);
+
+    //6
+    data.extend_from_slice(&[0xF0, 0x80, 0xF0, 0x90, 0xF0]);
+
+    //7
+    data.extend_from_slice(&[0xF0, 0x10, 0x20, 0x40, 0x40]);
+
+    //8
+    data.extend_from_slice(&[0xF0, 0x90, 0xF0, 0x90, 0xF0]);
+
+    //9
+    data.extend_from_slice(&[0xF0, 0x90, 0xF0, 0x10, 0xF0]);
+
+    //A
+    data.extend_from_slice(&[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+
+    //B
+    data.extend_from_slice(&[0xE0, 0x90, 0xE0, 0x90, 0xE0]);
+
+    //C
+    data.extend_from_slice(&[0xF0, 0x80, 0x80, 0x80, 0xF0]);
+
+    //D
+    data.extend_from_slice(&[0xE0, 0x90, 0x90, 0x90, 0xE0]);
+
+    //E
+    data.extend_from_slice(&[0xF0, 0x80, 0xF0, 0x80, 0xF0]);
+
+    //F
+    data.extend_from_slice(&[0xF0, 0x80, 0xF0, 0x80, 0x80]);
+
+    data
+}
+
+//SUPER-CHIP 8x10 digits used by FX30
+#[allow(dead_code)]
+fn create_big_font_data() -> Vec<u8>
+{
+    let mut data : Vec<u8> = Vec::with_capacity(10*16);
+
+    //0
+    data.extend_from_slice(&[0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C]);
+
+    //1
+    data.extend_from_slice(&[0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C]);
+
+    //2
+    data.extend_from_slice(&[0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF]);
+
+    //3
+    data.extend_from_slice(&[0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C]);
+
+    //4
+    data.extend_from_slice(&[0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06]);
+
+    //5
+    data.extend_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C]);
+
+    //6
+    data.extend_from_slice(&[0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C]);
+
+    //7
+    data.extend_from_slice(&[0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60]);
+
+    //8
+    data.extend_from_slice(&[0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C]);
+
+    //9
+    data.extend_from_slice(&[0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C]);
+
+    //A
+    data.extend_from_slice(&[0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3]);
+
+    //B
+    data.extend_from_slice(&[0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC]);
+
+    //C
+    data.extend_from_slice(&[0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C]);
+
+    //D
+    data.extend_from_slice(&[0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC]);
+
+    //E
+    data.extend_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF]);
+
+    //F
+    data.extend_from_slice(&[0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0]);
+
+    data
+}
+
+//With Quirks::stack_in_memory the return addresses grow down from there, like on the VIP
+const MEMORY_STACK_TOP : usize = 0xED0;
+const LOW_RESOLUTION_WIDTH : usize = 64;
+const LOW_RESOLUTION_HEIGHT : usize = 32;
+const HIGH_RESOLUTION_WIDTH : usize = 128;
+const HIGH_RESOLUTION_HEIGHT : usize = 64;
+
+//RGBA colors for each combination of the two XO-CHIP bitplanes
+pub type Palette = [[u8; 4]; 4];
+
+pub const DEFAULT_PALETTE : Palette =
+[
+    [0, 0, 0, 255],
+    [255, 255, 255, 255],
+    [170, 170, 170, 255],
+    [85, 85, 85, 255],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAccessKind
+{
+    Read,
+    Write,
+}
+
+//length bytes starting at address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess
+{
+    pub kind : MemoryAccessKind,
+    pub address : usize,
+    pub length : usize,
+}
+
+//Copy of the machine state visible to programs, for tools that want to inspect or diff it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State
+{
+    pub registers : [u8; 16],
+    pub address_register : u16,
+    pub program_counter : u16,
+    //Return addresses, the innermost call last
+    pub stack : Vec<u16>,
+    pub delay_timer : u8,
+    pub sound_timer : u8,
+    //Bit n is set while key n is held
+    pub keys : u16,
+    //None if the program counter is outside of memory
+    pub opcode : Option<u16>,
+    pub instruction_count : u64,
+}
+
+#[allow(dead_code)]
+pub struct Chip8
+{
+    memory : Vec<u8>,
+    registers : Vec<u8>,
+    address_register : u16,
+    program_counter : u16,
+    delay_timer : u8,
+    sound_timer : u8,
+    stack : Vec<u16>,
+    //each pixel holds one bit per bitplane
+    screen : Vec<u8>,
+    selected_planes : u8,
+    keys : Vec<bool>,
+    key_waiting_release : Option<u8>,
+    font_data_base_address : u16,
+    big_font_data_base_address : u16,
+    high_resolution : bool,
+    halted : bool,
+    rpl_flags : Vec<u8>,
+    audio_pattern : Vec<u8>,
+    audio_pitch : u8,
+    quirks : Quirks,
+    random : RandomGenerator,
+    unknown_opcode_policy : UnknownOpcodePolicy,
+    waiting_for_display_refresh : bool,
+    //identifies the rom in save states
+    rom_hash : u32,
+    //instructions executed since the start, to rewind or trace by instruction
+    instruction_count : u64,
+    tracer : Option<Tracer>,
+    palette : Palette,
+}
+
+impl Chip8
+{
+    #[allow(dead_code)]
+    pub fn new(rom_content : &[u8], quirks_ : Quirks, random_ : RandomGenerator) -> Result<Chip8, Chip8Error>
+    {
+        if quirks_.memory_size < MIN_MEMORY_SIZE || quirks_.memory_size > MAX_MEMORY_SIZE
+        {
+            return Err(Chip8Error::UnsupportedMemorySize { size : quirks_.memory_size });
+        }
+        let max_rom_size = quirks_.memory_size - 0x200;
+        if rom_content.len() > max_rom_size
+        {
+            return Err(Chip8Error::RomTooLarge { size : rom_content.len(), max_size : max_rom_size });
+        }
+
+        let mut chip = Chip8
+        {
+            registers : vec![0; 16],
+            address_register : 0,
+
+            delay_timer : 0,
+            sound_timer : 0,
+
+            program_counter : 0x200,
+
+            stack : Vec::new(),
+            screen : vec![0; LOW_RESOLUTION_WIDTH * LOW_RESOLUTION_HEIGHT],
+            selected_planes : 1,
+            keys : vec![false; 16],
+            key_waiting_release : None,
+            memory :  vec![0; quirks_.memory_size],
+            font_data_base_address : 0,
+            big_font_data_base_address : 80,
+            high_resolution : false,
+            halted : false,
+            rpl_flags : vec![0; 16],
+            audio_pattern : vec![0; 16],
+            audio_pitch : 64,
+            quirks : quirks_,
+            random : random_,
+            unknown_opcode_policy : UnknownOpcodePolicy::Skip,
+            waiting_for_display_refresh : false,
+            rom_hash : savestate::crc32(rom_content),
+            instruction_count : 0,
+            tracer : None,
+            palette : DEFAULT_PALETTE,
+        };
+
+        let font_data = create_font_data();
+        let font_address = chip.font_data_base_address as usize;
+        chip.memory[font_address .. font_address + font_data.len()].copy_from_slice(&font_data);
+
+        let big_font_data = create_big_font_data();
+        let big_font_address = chip.big_font_data_base_address as usize;
+        chip.memory[big_font_address .. big_font_address + big_font_data.len()].copy_from_slice(&big_font_data);
+
+        let rom_address = chip.program_counter as usize;
+        chip.memory[rom_address .. rom_address + rom_content.len()].copy_from_slice(rom_content);
+
+        Ok(chip)
+    }
+
+    #[allow(dead_code)]
+    pub fn press_key(&mut self, key : u8)
+    {
+        if (key as usize) < self.keys.len()
+        {
+            self.keys[key as usize] = true;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn release_key(&mut self, key : u8)
+    {
+        if (key as usize) < self.keys.len()
+        {
+            self.keys[key as usize] = false;
+        }
+    }
+
+    //Only changes how the screen is shown, it is not part of save states
+    #[allow(dead_code)]
+    pub fn set_palette(&mut self, palette : Palette)
+    {
+        self.palette = palette;
+    }
+
+    #[allow(dead_code)]
+    pub fn is_key_pressed(&self, key : u8) -> bool
+    {
+        self.keys.get(key as usize).cloned().unwrap_or(false)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_key(&mut self, key : u8, pressed : bool)
+    {
+        if pressed
+        {
+            self.press_key(key);
+        }
+        else
+        {
+            self.release_key(key);
+        }
+    }
+
+    //Bit n is set while key n is held
+    #[allow(dead_code)]
+    pub fn keys(&self) -> u16
+    {
+        let mut keys = 0;
+        for (key, &pressed) in self.keys.iter().enumerate()
+        {
+            if pressed
+            {
+                keys |= 1 << key;
+            }
+        }
+        keys
+    }
+
+    #[allow(dead_code)]
+    pub fn set_keys(&mut self, keys : u16)
+    {
+        for key in 0 .. self.keys.len()
+        {
+            self.keys[key] = keys & 1 << key != 0;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_unknown_opcode_policy(&mut self, policy : UnknownOpcodePolicy)
+    {
+        self.unknown_opcode_policy = policy;
+    }
+
+    #[allow(dead_code)]
+    pub fn run_one_cycle(&mut self) -> Result<(), Chip8Error>
+    {
+        if !self.waiting_for_display_refresh && !self.halted
+        {
+            self.random.step();
+            let program_counter = self.program_counter;
+            let opcode = self.fetch_opcode()?;
+            self.execute_instruction(Instruction::decode(opcode))?;
+            self.trace(program_counter, opcode);
+            self.instruction_count += 1;
+        }
+        Ok(())
+    }
+
+    //Replaces the current tracer, which is returned so that it can be finished
+    #[allow(dead_code)]
+    pub fn set_tracer(&mut self, tracer : Option<Tracer>) -> Option<Tracer>
+    {
+        mem::replace(&mut self.tracer, tracer)
+    }
+
+    fn trace(&mut self, program_counter : u16, opcode : u16)
+    {
+        if self.tracer.is_none()
+        {
+            return;
+        }
+        let long_address = match Instruction::decode(opcode)
+        {
+            Instruction::LdILong => self.read_word(program_counter as usize + 2).ok(),
+            _ => None,
+        };
+        if let Some(ref mut tracer) = self.tracer
+        {
+            let mut registers = [0; 16];
+            registers.copy_from_slice(&self.registers);
+            tracer.trace(&TraceEntry
+            {
+                cycle : self.instruction_count,
+                program_counter,
+                opcode,
+                long_address,
+                registers,
+                address_register : self.address_register,
+                stack_depth : self.stack.len() as u8,
+            });
+        }
+    }
+
+    //Runs the instructions of one 60Hz frame, fewer if the program waits for the display
+    //or halts, then ticks the timers
+    #[allow(dead_code)]
+    pub fn run_frame(&mut self, instructions_per_frame : u32) -> Result<(), Chip8Error>
+    {
+        for _ in 0 .. instructions_per_frame
+        {
+            if self.waiting_for_display_refresh || self.halted
+            {
+                break;
+            }
+            self.run_one_cycle()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    //Has to be called at 60Hz of emulated time
+    #[allow(dead_code)]
+    pub fn tick_timers(&mut self)
+    {
+        if self.delay_timer > 0
+        {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0
+        {
+            self.sound_timer -= 1;
+        }
+        self.waiting_for_display_refresh = false;
+    }
+
+    //The tone plays as long as the sound timer is not 0
+    #[allow(dead_code)]
+    pub fn is_sound_playing(&self) -> bool
+    {
+        self.sound_timer > 0
+    }
+
+    #[allow(dead_code)]
+    fn fetch_opcode(&self) -> Result<u16, Chip8Error>
+    {
+        self.read_word(self.program_counter as usize)
+    }
+
+    fn read_memory(&self, address : usize) -> Result<u8, Chip8Error>
+    {
+        match self.memory.get(address)
+        {
+            Some(value) => Ok(*value),
+            None => Err(Chip8Error::MemoryOutOfRange { program_counter : self.program_counter, address }),
+        }
+    }
+
+    fn write_memory(&mut self, address : usize, value : u8) -> Result<(), Chip8Error>
+    {
+        if address >= self.memory.len()
+        {
+            return Err(Chip8Error::MemoryOutOfRange { program_counter : self.program_counter, address });
+        }
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    fn read_word(&self, address : usize) -> Result<u16, Chip8Error>
+    {
+        let upper_byte  = self.read_memory(address)? as u16;
+        let lower_byte = self.read_memory(address + 1)? as u16;
+        Ok(upper_byte << 8 | lower_byte)
+    }
+
+    //Running off the top of the 16 bit address space is reported like any other access outside of memory
+    fn advance_program_counter(&mut self, length : u16) -> Result<(), Chip8Error>
+    {
+        self.program_counter = self.address_after(self.program_counter, length)?;
+        Ok(())
+    }
+
+    fn address_after(&self, address : u16, length : u16) -> Result<u16, Chip8Error>
+    {
+        match address.checked_add(length)
+        {
+            Some(next_address) => Ok(next_address),
+            None => Err(Chip8Error::MemoryOutOfRange { program_counter : self.program_counter, address : address as usize + length as usize }),
+        }
+    }
+
+    //In XO-CHIP mode skips also have to jump over the 4 bytes long F000 NNNN instruction
+    fn skip_next_instruction(&mut self) -> Result<(), Chip8Error>
+    {
+        let next_instruction = self.program_counter as usize + 2;
+        if self.quirks.is_xo_chip() && self.read_word(next_instruction).ok() == Some(0xF000)
+        {
+            self.advance_program_counter(6)
+        }
+        else
+        {
+            self.advance_program_counter(4)
+        }
+    }
+
+    #[allow(dead_code)]
+    fn execute_instruction(&mut self, instruction : Instruction) -> Result<(), Chip8Error>
+    {
+        match instruction
+        {
+            //00E0
+            Instruction::Cls =>
+            {
+                for i in 0 .. self.screen.len()
+                {
+                    self.screen[i] &= !self.selected_planes;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //00EE
+            Instruction::Ret =>
+            {
+                let call_address = match self.stack.pop()
+                {
+                    Some(address) => address,
+                    None => return Err(Chip8Error::StackUnderflow { program_counter : self.program_counter }),
+                };
+                //ROMs that rewrite the stack in memory return where they chose
+                self.program_counter = if self.quirks.stack_in_memory
+                {
+                    self.read_word(memory_stack_address(self.stack.len()))?
+                }
+                else
+                {
+                    self.address_after(call_address, 2)?
+                };
+            }
+            //00CN
+            Instruction::ScrollDown(n) =>
+            {
+                self.scroll_down(n as usize);
+                self.advance_program_counter(2)?;
+            }
+            //00FB
+            Instruction::ScrollRight =>
+            {
+                self.scroll_right(4);
+                self.advance_program_counter(2)?;
+            }
+            //00FC
+            Instruction::ScrollLeft =>
+            {
+                self.scroll_left(4);
+                self.advance_program_counter(2)?;
+            }
+            //00FD
+            Instruction::Exit =>
+            {
+                self.halted = true;
+            }
+            //00FE
+            Instruction::LowResolution =>
+            {
+                self.set_high_resolution(false);
+                self.advance_program_counter(2)?;
+            }
+            //00FF
+            Instruction::HighResolution =>
+            {
+                self.set_high_resolution(true);
+                self.advance_program_counter(2)?;
+            }
+            //1NNN
+            Instruction::Jp(nnn) =>
+            {
+                self.program_counter = nnn;
+            }
+            //2NNN
+            Instruction::Call(nnn) =>
+            {
+                if let Some(depth) = self.quirks.stack_depth
+                {
+                    if self.stack.len() >= depth
+                    {
+                        return Err(Chip8Error::StackOverflow { program_counter : self.program_counter, depth });
+                    }
+                }
+                if self.quirks.stack_in_memory
+                {
+                    let address = memory_stack_address(self.stack.len());
+                    let return_address = self.address_after(self.program_counter, 2)?;
+                    self.write_memory(address, (return_address >> 8) as u8)?;
+                    self.write_memory(address + 1, return_address as u8)?;
+                }
+                self.stack.push(self.program_counter);
+                self.program_counter = nnn;
+            }
+            //3XNN
+            Instruction::SeByte { x, byte } =>
+            {
+                if self.registers[x as usize] == byte
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //4XNN
+            Instruction::SneByte { x, byte } =>
+            {
+                if self.registers[x as usize] != byte
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //5XY0
+            Instruction::SeRegister { x, y } =>
+            {
+                if self.registers[x as usize] == self.registers[y as usize]
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //5XY2
+            Instruction::SaveRange { x, y } =>
+            {
+                let registers = register_range(x as usize, y as usize);
+                for (i, &register) in registers.iter().enumerate()
+                {
+                    let address = self.address_register as usize + i;
+                    let value = self.registers[register];
+                    self.write_memory(address, value)?;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //5XY3
+            Instruction::LoadRange { x, y } =>
+            {
+                let registers = register_range(x as usize, y as usize);
+                for (i, &register) in registers.iter().enumerate()
+                {
+                    self.registers[register] = self.read_memory(self.address_register as usize + i)?;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //6XNN
+            Instruction::LdByte { x, byte } =>
+            {
+                self.registers[x as usize] = byte;
+                self.advance_program_counter(2)?;
+            }
+            //7XNN
+            Instruction::AddByte { x, byte } =>
+            {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(byte);
+                self.advance_program_counter(2)?;
+            }
+            //8XY0
+            Instruction::LdRegister { x, y } =>
+            {
+                self.registers[x as usize] = self.registers[y as usize];
+                self.advance_program_counter(2)?;
+            }
+            //8XY1
+            Instruction::Or { x, y } =>
+            {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.vf_reset
+                {
+                    self.registers[15] = 0;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XY2
+            Instruction::And { x, y } =>
+            {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.vf_reset
+                {
+                    self.registers[15] = 0;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XY3
+            Instruction::Xor { x, y } =>
+            {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.vf_reset
+                {
+                    self.registers[15] = 0;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XY4
+            Instruction::AddRegister { x, y } =>
+            {
+                let has_carry = (self.registers[x as usize] as u16 + self.registers[y as usize] as u16) > 255;
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(self.registers[y as usize]);
+                if has_carry
+                {
+                    self.registers[15] = 1;
+                }
+                else
+                {
+                    self.registers[15] = 0;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XY5
+            Instruction::Sub { x, y } =>
+            {
+                let has_borrow = self.registers[x as usize] < self.registers[y as usize];
+                self.registers[x as usize] = self.registers[x as usize].wrapping_sub(self.registers[y as usize]);
+                if has_borrow
+                {
+                    self.registers[15] = 0;
+                }
+                else
+                {
+                    self.registers[15] = 1;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XY6
+            Instruction::Shr { x, y } =>
+            {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let lest_significant_bit = self.registers[source as usize] & 0b00000001;
+                self.registers[x as usize] = self.registers[source as usize] >> 1;
+                self.registers[15] = lest_significant_bit;
+                self.advance_program_counter(2)?;
+            }
+            //8XY7
+            Instruction::Subn { x, y } =>
+            {
+                let has_borrow = self.registers[y as usize] < self.registers[x as usize];
+                self.registers[x as usize] = self.registers[y as usize].wrapping_sub(self.registers[x as usize]);
+                if has_borrow
+                {
+                    self.registers[15] = 0;
+                }
+                else
+                {
+                    self.registers[15] = 1;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //8XYE
+            Instruction::Shl { x, y } =>
+            {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let most_significant_bit = (self.registers[source as usize] & 0b10000000) >> 7;
+                self.registers[x as usize] = self.registers[source as usize] << 1;
+                self.registers[15] = most_significant_bit;
+                self.advance_program_counter(2)?;
+            }
+            //9XY0
+            Instruction::SneRegister { x, y } =>
+            {
+                if self.registers[x as usize] != self.registers[y as usize]
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //ANNN
+            Instruction::LdI(nnn) =>
+            {
+                self.address_register = nnn;
+                self.advance_program_counter(2)?;
+            }
+            //BNNN
+            Instruction::JpOffset(nnn) =>
+            {
+                let x = (nnn & 0x0F00) >> 8;
+                let offset_register = if self.quirks.jump_uses_vx { x } else { 0 };
+                self.program_counter = nnn + self.registers[offset_register as usize] as u16;
+            }
+            //CXNN
+            Instruction::Rnd { x, byte } =>
+            {
+                let random_number = self.random.next_byte();
+                self.registers[x as usize] =  random_number & byte;
+                self.advance_program_counter(2)?;
+            }
+            //DXYN
+            Instruction::Drw { x, y, n } =>
+            {
+                /*
+                Draw a sprite at position VX, VY with N bytes of sprite data starting at the address stored in I
+                Set VF to 01 if any set pixels are changed to unset, and 00 otherwise.
+                CHIP-8 sprites are always eight pixels wide and between one to fifteen pixels high.
+                */
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
+
+                //DXY0 draws a 16x16 sprite on SUPER-CHIP
+                let has_changed_set_pixel_to_unset = if n == 0
+                {
+                    self.draw_sprite(vx, vy, 16, 16)?
+                }
+                else
+                {
+                    self.draw_sprite(vx, vy, n as usize, 8)?
+                };
+
+                if has_changed_set_pixel_to_unset
+                {
+                    self.registers[15] = 1;
+                }
+                else
+                {
+                    self.registers[15] = 0;
+                }
+                if self.quirks.display_wait
+                {
+                    self.waiting_for_display_refresh = true;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //EX9E
+            Instruction::Skp(x) =>
+            {
+                if self.keys[(self.registers[x as usize] & 0x0F) as usize]
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //EXA1
+            Instruction::Sknp(x) =>
+            {
+                if !self.keys[(self.registers[x as usize] & 0x0F) as usize]
+                {
+                    self.skip_next_instruction()?;
+                }
+                else
+                {
+                    self.advance_program_counter(2)?;
+                }
+            }
+            //F000 NNNN
+            Instruction::LdILong =>
+            {
+                self.address_register = self.read_word(self.program_counter as usize + 2)?;
+                self.advance_program_counter(4)?;
+            }
+            //FN01
+            Instruction::Plane(n) =>
+            {
+                self.selected_planes = n & 0x3;
+                self.advance_program_counter(2)?;
+            }
+            //F002
+            Instruction::Audio =>
+            {
+                for i in 0 .. self.audio_pattern.len()
+                {
+                    self.audio_pattern[i] = self.read_memory(self.address_register as usize + i)?;
+                }
+                self.advance_program_counter(2)?;
+            }
+            //FX07
+            Instruction::LdFromDelayTimer(x) =>
+            {
+                self.registers[x as usize] = self.delay_timer;
+                self.advance_program_counter(2)?;
+            }
+            //FX0A
+            Instruction::WaitKey(x) =>
+            {
+                //The original interpreter waits for a key to be pressed and then released
+                //before storing it, so a held key does not retrigger the instruction.
+                match self.key_waiting_release
+                {
+                    Some(key) =>
+                    {
+                        if !self.keys[key as usize]
+                        {
+                            self.registers[x as usize] = key;
+                            self.key_waiting_release = None;
+                            self.advance_program_counter(2)?;
+                        }
+                    }
+                    None =>
+                    {
+                        for i in 0 .. self.keys.len()
+                        {
+                            if self.keys[i]
+                            {
+                                self.key_waiting_release = Some(i as u8);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            //FX15
+            Instruction::LdDelayTimer(x) =>
+            {
+                self.delay_timer = self.registers[x as usize];
+                self.advance_program_counter(2)?;
+            }
+            //FX18
+            Instruction::LdSoundTimer(x) =>
+            {
+                self.sound_timer = self.registers[x as usize];
+                self.advance_program_counter(2)?;
+            }
+            //FX1E
+            Instruction::AddI(x) =>
+            {
+                self.address_register = self.address_register.wrapping_add(self.registers[x as usize] as u16);
+                self.advance_program_counter(2)?;
+            }
+            //FX29
+            Instruction::LdFont(x) =>
+            {
+                self.address_register = self.font_data_base_address + (5 * (self.registers[x as usize] & 0x0F) as u16);
+                self.advance_program_counter(2)?;
+            }
+            //FX30
+            Instruction::LdBigFont(x) =>
+            {
+                self.address_register = self.big_font_data_base_address + (10 * (self.registers[x as usize] & 0x0F) as u16);
+                self.advance_program_counter(2)?;
+            }
+            //FX33
+            Instruction::Bcd(x) =>
+            {
+                let hundreds : u8 = self.registers[x as usize] / 100;
+                let tens : u8 = (self.registers[x as usize] - hundreds * 100) / 10;
+                let ones : u8 = (self.registers[x as usize] - hundreds * 100) - tens * 10;
+                let address = self.address_register as usize;
+                self.write_memory(address, hundreds)?;
+                self.write_memory(address + 1, tens)?;
+                self.write_memory(address + 2, ones)?;
+                self.advance_program_counter(2)?;
+            }
+            //FX3A
+            Instruction::Pitch(x) =>
+            {
+                self.audio_pitch = self.registers[x as usize];
+                self.advance_program_counter(2)?;
+            }
+            //FX55
+            Instruction::Store(x) =>
+            {
+                for i in 0 .. x as usize + 1
+                {
+                    let address = self.address_register as usize + i;
+                    let value = self.registers[i];
+                    self.write_memory(address, value)?;
+                }
+                self.increment_address_register_after_load_store(x);
+                self.advance_program_counter(2)?;
+            }
+            //FX65
+            Instruction::Load(x) =>
+            {
+                for i in 0 .. x as usize + 1
+                {
+                    self.registers[i] = self.read_memory(self.address_register as usize + i)?;
+                }
+                self.increment_address_register_after_load_store(x);
+                self.advance_program_counter(2)?;
+            }
+            //FX75
+            Instruction::StoreFlags(x) =>
+            {
+                for i in 0 .. x as usize + 1
+                {
+                    self.rpl_flags[i] = self.registers[i];
+                }
+                self.advance_program_counter(2)?;
+            }
+            //FX85
+            Instruction::LoadFlags(x) =>
+            {
+                for i in 0 .. x as usize + 1
+                {
+                    self.registers[i] = self.rpl_flags[i];
+                }
+                self.advance_program_counter(2)?;
+            }
+            //0NNN and other
+            Instruction::Sys(_) | Instruction::Unknown(_) =>
+            {
+                match self.unknown_opcode_policy
+                {
+                    UnknownOpcodePolicy::Skip => self.advance_program_counter(2)?,
+                    UnknownOpcodePolicy::Halt => self.halted = true,
+                    UnknownOpcodePolicy::Error => return Err(Chip8Error::UnknownOpcode { program_counter : self.program_counter, opcode : instruction.encode() }),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn increment_address_register_after_load_store(&mut self, x : u8)
+    {
+        match self.quirks.load_store
+        {
+            LoadStoreQuirk::Unchanged => {},
+            LoadStoreQuirk::IncrementByX => self.address_register = self.address_register.wrapping_add(x as u16),
+            LoadStoreQuirk::IncrementByXPlusOne => self.address_register = self.address_register.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    //Returns true when a set pixel was turned off.
+    //Each selected bitplane consumes its own block of sprite data, one after the other.
+    fn draw_sprite(&mut self, vx : u8, vy : u8, height : usize, width : usize) -> Result<bool, Chip8Error>
+    {
+        let screen_width = self.screen_width() as usize;
+        let screen_height = self.screen_height() as usize;
+        let bytes_per_row = width / 8;
+
+        let origin_x = vx as usize % screen_width;
+        let origin_y = vy as usize % screen_height;
+
+        let mut has_changed_set_pixel_to_unset = false;
+        let mut sprite_address = self.address_register as usize;
+
+        for plane in 0 .. 2
+        {
+            let plane_bit : u8 = 1 << plane;
+            if self.selected_planes & plane_bit == 0
+            {
+                continue;
+            }
+
+            for i in 0 .. height
+            {
+                let mut y = origin_y + i;
+                if y >= screen_height
+                {
+                    if self.quirks.clip_sprites
+                    {
+                        break;
+                    }
+                    y %= screen_height;
+                }
+
+                let mut sprite_row : u16 = 0;
+                for b in 0 .. bytes_per_row
+                {
+                    let address = sprite_address + i * bytes_per_row + b;
+                    sprite_row = (sprite_row << 8) | self.read_memory(address)? as u16;
+                }
+
+                for j in 0 .. width
+                {
+                    let mut x = origin_x + j;
+                    if x >= screen_width
+                    {
+                        if self.quirks.clip_sprites
+                        {
+                            break;
+                        }
+                        x %= screen_width;
+                    }
+
+                    let sprite_pixel = sprite_row & (0b1 << (width - 1 - j));
+                    if sprite_pixel != 0
+                    {
+                        if self.screen[y * screen_width + x] & plane_bit != 0
+                        {
+                            has_changed_set_pixel_to_unset = true;
+                        }
+                        self.screen[y * screen_width + x] ^= plane_bit;
+                    }
+                }
+            }
+
+            sprite_address += height * bytes_per_row;
+        }
+
+        Ok(has_changed_set_pixel_to_unset)
+    }
+
+    fn set_high_resolution(&mut self, high_resolution : bool)
+    {
+        self.high_resolution = high_resolution;
+        let size = (self.screen_width() * self.screen_height()) as usize;
+        self.screen = vec![0; size];
+    }
+
+    fn scroll_down(&mut self, n : usize)
+    {
+        let width = self.screen_width() as usize;
+        let height = self.screen_height() as usize;
+        for y in (0 .. height).rev()
+        {
+            for x in 0 .. width
+            {
+                let source = if y >= n { self.screen[(y - n) * width + x] } else { 0 };
+                self.screen[y * width + x] = (self.screen[y * width + x] & !self.selected_planes) | (source & self.selected_planes);
+            }
+        }
+    }
+
+    fn scroll_right(&mut self, n : usize)
+    {
+        let width = self.screen_width() as usize;
+        let height = self.screen_height() as usize;
+        for y in 0 .. height
+        {
+            for x in (0 .. width).rev()
+            {
+                let source = if x >= n { self.screen[y * width + x - n] } else { 0 };
+                self.screen[y * width + x] = (self.screen[y * width + x] & !self.selected_planes) | (source & self.selected_planes);
+            }
+        }
+    }
+
+    fn scroll_left(&mut self, n : usize)
+    {
+        let width = self.screen_width() as usize;
+        let height = self.screen_height() as usize;
+        for y in 0 .. height
+        {
+            for x in 0 .. width
+            {
+                let source = if x + n < width { self.screen[y * width + x + n] } else { 0 };
+                self.screen[y * width + x] = (self.screen[y * width + x] & !self.selected_planes) | (source & self.selected_planes);
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn audio_pattern(&self) -> &[u8]
+    {
+        &self.audio_pattern
+    }
+
+    //XO-CHIP pattern playback rate in bits per second, 4000 at the default pitch of 64
+    #[allow(dead_code)]
+    pub fn audio_playback_rate(&self) -> f64
+    {
+        4000.0 * 2.0f64.powf((self.audio_pitch as f64 - 64.0) / 48.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_halted(&self) -> bool
+    {
+        self.halted
+    }
+
+    #[allow(dead_code)]
+    pub fn is_high_resolution(&self) -> bool
+    {
+        self.high_resolution
+    }
+
+    #[allow(dead_code)]
+    pub fn screen_width(&self) -> u32
+    {
+        if self.high_resolution
+        {
+            HIGH_RESOLUTION_WIDTH as u32
+        }
+        else
+        {
+            LOW_RESOLUTION_WIDTH as u32
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn screen_height(&self) -> u32
+    {
+        if self.high_resolution
+        {
+            HIGH_RESOLUTION_HEIGHT as u32
+        }
+        else
+        {
+            LOW_RESOLUTION_HEIGHT as u32
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_video_buffer_as_rgba(&self) -> Vec<u8>
+    {
+        let print_debug = false;
+        let mut image_data : Vec<u8> = Vec::with_capacity( (self.screen_width() * self.screen_height() * 4) as usize );
+        for j in 0 .. self.screen_height()
+        {
+            for i in 0 .. self.screen_width()
+            {
+                let u = i;
+                let v = self.screen_height() -1 - j;
+                let color = self.palette[(self.screen[(u + v * self.screen_width()) as usize] & 0x3) as usize];
+                image_data.extend_from_slice(&color);
+
+                if print_debug
+                {
+                    print!("{}", self.screen[(i + j * self.screen_width()) as usize]);
+                }
+            }
+            if print_debug
+            {
+                print!("\r\n");
+            }
+        }
+        if print_debug
+        {
+            print!("\r\n");
+        }
+        image_data
+    }
+
+    #[allow(dead_code)]
+    pub fn program_counter(&self) -> u16
+    {
+        self.program_counter
+    }
+
+    #[allow(dead_code)]
+    pub fn address_register(&self) -> u16
+    {
+        self.address_register
+    }
+
+    #[allow(dead_code)]
+    pub fn registers(&self) -> &[u8]
+    {
+        &self.registers
+    }
+
+    #[allow(dead_code)]
+    pub fn delay_timer(&self) -> u8
+    {
+        self.delay_timer
+    }
+
+    #[allow(dead_code)]
+    pub fn sound_timer(&self) -> u8
+    {
+        self.sound_timer
+    }
+
+    //Return addresses, the innermost call last
+    #[allow(dead_code)]
+    pub fn stack(&self) -> &[u16]
+    {
+        &self.stack
+    }
+
+    #[allow(dead_code)]
+    pub fn memory(&self) -> &[u8]
+    {
+        &self.memory
+    }
+
+    #[allow(dead_code)]
+    pub fn stack_depth(&self) -> usize
+    {
+        self.stack.len()
+    }
+
+    //length bytes starting at address, None if any of them is outside of memory
+    #[allow(dead_code)]
+    pub fn memory_range(&self, address : usize, length : usize) -> Option<&[u8]>
+    {
+        let end = address.checked_add(length)?;
+        self.memory.get(address .. end)
+    }
+
+    #[allow(dead_code)]
+    pub fn read_byte(&self, address : usize) -> Result<u8, Chip8Error>
+    {
+        self.read_memory(address)
+    }
+
+    //Opcode at the program counter, None if it is outside of memory
+    #[allow(dead_code)]
+    pub fn current_opcode(&self) -> Option<u16>
+    {
+        self.fetch_opcode().ok()
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self) -> State
+    {
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&self.registers);
+        State
+        {
+            registers,
+            address_register : self.address_register,
+            program_counter : self.program_counter,
+            stack : self.stack.clone(),
+            delay_timer : self.delay_timer,
+            sound_timer : self.sound_timer,
+            keys : self.keys(),
+            opcode : self.current_opcode(),
+            instruction_count : self.instruction_count,
+        }
+    }
+
+    /*
+    Setters for debuggers. They bypass the instruction semantics entirely, so for example
+    changing the program counter does not touch the stack.
+    */
+    #[allow(dead_code)]
+    pub fn set_register(&mut self, x : u8, value : u8)
+    {
+        self.registers[x as usize & 0xF] = value;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_address_register(&mut self, value : u16)
+    {
+        self.address_register = value;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_program_counter(&mut self, value : u16)
+    {
+        self.program_counter = value;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_delay_timer(&mut self, value : u8)
+    {
+        self.delay_timer = value;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_sound_timer(&mut self, value : u8)
+    {
+        self.sound_timer = value;
+    }
+
+    //Drops the innermost return addresses or pushes 0 until the stack has depth entries
+    #[allow(dead_code)]
+    pub fn set_stack_depth(&mut self, depth : usize) -> Result<(), Chip8Error>
+    {
+        if let Some(max_depth) = self.quirks.stack_depth
+        {
+            if depth > max_depth
+            {
+                return Err(Chip8Error::StackOverflow { program_counter : self.program_counter, depth : max_depth });
+            }
+        }
+        self.stack.resize(depth, 0);
+        Ok(())
+    }
+
+    //Replaces the whole stack, given as in stack(). With the stack in memory the copies there are rewritten too
+    #[allow(dead_code)]
+    pub fn set_stack(&mut self, stack : &[u16]) -> Result<(), Chip8Error>
+    {
+        if let Some(&address) = stack.iter().find(|&&address| !is_call_address(address, self.memory.len()))
+        {
+            return Err(Chip8Error::MemoryOutOfRange { program_counter : self.program_counter, address : address as usize });
+        }
+        self.set_stack_depth(stack.len())?;
+        self.stack.copy_from_slice(stack);
+        if self.quirks.stack_in_memory
+        {
+            for (depth, call_address) in stack.iter().enumerate()
+            {
+                let address = memory_stack_address(depth);
+                let return_address = call_address.wrapping_add(2);
+                self.write_memory(address, (return_address >> 8) as u8)?;
+                self.write_memory(address + 1, return_address as u8)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn write_byte(&mut self, address : usize, value : u8) -> Result<(), Chip8Error>
+    {
+        self.write_memory(address, value)
+    }
+
+    //Restores everything State holds except the opcode and instruction count, which follow from memory and history
+    #[allow(dead_code)]
+    pub fn set_state(&mut self, state : &State) -> Result<(), Chip8Error>
+    {
+        self.set_stack(&state.stack)?;
+        self.registers.copy_from_slice(&state.registers);
+        self.address_register = state.address_register;
+        self.program_counter = state.program_counter;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.set_keys(state.keys);
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn set_memory(&mut self, address : usize, data : &[u8]) -> Result<(), Chip8Error>
+    {
+        if address.checked_add(data.len()).is_none_or(|end| end > self.memory.len())
+        {
+            return Err(Chip8Error::MemoryOutOfRange { program_counter : self.program_counter, address : address.saturating_add(data.len().saturating_sub(1)) });
+        }
+        self.memory[address .. address + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    //Instruction at the program counter, None if it is outside of memory
+    #[allow(dead_code)]
+    pub fn current_instruction(&self) -> Option<Instruction>
+    {
+        self.fetch_opcode().ok().map(Instruction::decode)
+    }
+
+    //Data the instruction at the program counter is about to read or write, for watchpoints
+    #[allow(dead_code)]
+    pub fn next_memory_access(&self) -> Option<MemoryAccess>
+    {
+        let address = self.address_register as usize;
+        let planes = (self.selected_planes & 1) as usize + (self.selected_planes >> 1 & 1) as usize;
+        let (kind, length) = match self.current_instruction()?
+        {
+            //The return addresses of the VIP stack, below MEMORY_STACK_TOP
+            Instruction::Call(_) if self.quirks.stack_in_memory =>
+            {
+                return Some(MemoryAccess { kind : MemoryAccessKind::Write, address : memory_stack_address(self.stack.len()), length : 2 });
+            }
+            Instruction::Ret if self.quirks.stack_in_memory && !self.stack.is_empty() =>
+            {
+                return Some(MemoryAccess { kind : MemoryAccessKind::Read, address : memory_stack_address(self.stack.len() - 1), length : 2 });
+            }
+            Instruction::SaveRange { x, y } => (MemoryAccessKind::Write, register_range(x as usize, y as usize).len()),
+            Instruction::LoadRange { x, y } => (MemoryAccessKind::Read, register_range(x as usize, y as usize).len()),
+            Instruction::Drw { n : 0, .. } => (MemoryAccessKind::Read, 32 * planes),
+            Instruction::Drw { n, .. } => (MemoryAccessKind::Read, n as usize * planes),
+            Instruction::Audio => (MemoryAccessKind::Read, self.audio_pattern.len()),
+            Instruction::Bcd(_) => (MemoryAccessKind::Write, 3),
+            Instruction::Store(x) => (MemoryAccessKind::Write, x as usize + 1),
+            Instruction::Load(x) => (MemoryAccessKind::Read, x as usize + 1),
+            _ => return None,
+        };
+        if length == 0
+        {
+            return None;
+        }
+        Some(MemoryAccess { kind, address, length })
+    }
+
+    #[allow(dead_code)]
+    pub fn instruction_count(&self) -> u64
+    {
+        self.instruction_count
+    }
+
+    #[allow(dead_code)]
+    pub fn is_waiting_for_display_refresh(&self) -> bool
+    {
+        self.waiting_for_display_refresh
+    }
+
+    #[allow(dead_code)]
+    pub fn rom_hash(&self) -> u32
+    {
+        self.rom_hash
+    }
+
+    #[allow(dead_code)]
+    pub fn quirks(&self) -> Quirks
+    {
+        self.quirks
+    }
+
+    //Save state payload, see savestate.rs for the framing
+    pub fn write_state(&self, writer : &mut StateWriter)
+    {
+        self.quirks.write_state(writer);
+        writer.u8(match self.unknown_opcode_policy
+        {
+            UnknownOpcodePolicy::Skip => 0,
+            UnknownOpcodePolicy::Halt => 1,
+            UnknownOpcodePolicy::Error => 2,
+        });
+        self.random.write_state(writer);
+
+        writer.byte_vec(&self.memory);
+        writer.bytes(&self.registers);
+        writer.u16(self.address_register);
+        writer.u16(self.program_counter);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.u32(self.stack.len() as u32);
+        for address in &self.stack
+        {
+            writer.u16(*address);
+        }
+
+        writer.bool(self.high_resolution);
+        writer.u8(self.selected_planes);
+        writer.byte_vec(&self.screen);
+
+        for key in &self.keys
+        {
+            writer.bool(*key);
+        }
+        writer.u8(self.key_waiting_release.unwrap_or(0xFF));
+
+        writer.bool(self.halted);
+        writer.bool(self.waiting_for_display_refresh);
+        writer.bytes(&self.rpl_flags);
+        writer.bytes(&self.audio_pattern);
+        writer.u8(self.audio_pitch);
+        writer.u64(self.instruction_count);
+    }
+
+    //Nothing is modified unless the whole payload is valid
+    pub fn read_state(&mut self, reader : &mut StateReader) -> Result<(), SaveStateError>
+    {
+        let quirks = Quirks::read_state(reader)?;
+        let unknown_opcode_policy = match reader.u8()?
+        {
+            0 => UnknownOpcodePolicy::Skip,
+            1 => UnknownOpcodePolicy::Halt,
+            2 => UnknownOpcodePolicy::Error,
+            value => return Err(SaveStateError::Corrupted(format!("unknown opcode policy {}", value))),
+        };
+        let random = RandomGenerator::read_state(reader)?;
+
+        let memory = reader.byte_vec()?;
+        if memory.len() != quirks.memory_size
+        {
+            return Err(SaveStateError::Corrupted(format!("{} bytes of memory for a memory size of {}", memory.len(), quirks.memory_size)));
+        }
+        let registers = reader.bytes(16)?.to_vec();
+        let address_register = reader.u16()?;
+        let program_counter = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let stack_length = reader.u32()? as usize;
+        if let Some(max_depth) = quirks.stack_depth
+        {
+            if stack_length > max_depth
+            {
+                return Err(SaveStateError::Corrupted(format!("stack depth {} is over {}", stack_length, max_depth)));
+            }
+        }
+        let mut stack = Vec::with_capacity(stack_length.min(reader.remaining() / 2));
+        for _ in 0 .. stack_length
+        {
+            let address = reader.u16()?;
+            if !is_call_address(address, quirks.memory_size)
+            {
+                return Err(SaveStateError::Corrupted(format!("call address {:#06X} on the stack is outside of memory", address)));
+            }
+            stack.push(address);
+        }
+
+        let high_resolution = reader.bool()?;
+        let selected_planes = reader.u8()?;
+        let screen = reader.byte_vec()?;
+        let screen_size = if high_resolution { HIGH_RESOLUTION_WIDTH * HIGH_RESOLUTION_HEIGHT } else { LOW_RESOLUTION_WIDTH * LOW_RESOLUTION_HEIGHT };
+        if screen.len() != screen_size
+        {
+            return Err(SaveStateError::Corrupted(format!("{} pixels for a screen of {}", screen.len(), screen_size)));
+        }
+
+        let mut keys = Vec::with_capacity(16);
+        for _ in 0 .. 16
+        {
+            keys.push(reader.bool()?);
+        }
+        let key_waiting_release = match reader.u8()?
+        {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            key => return Err(SaveStateError::Corrupted(format!("key {} does not exist", key))),
+        };
+
+        let halted = reader.bool()?;
+        let waiting_for_display_refresh = reader.bool()?;
+        let rpl_flags = reader.bytes(16)?.to_vec();
+        let audio_pattern = reader.bytes(16)?.to_vec();
+        let audio_pitch = reader.u8()?;
+        let instruction_count = reader.u64()?;
+
+        if !reader.is_at_end()
+        {
+            return Err(SaveStateError::Corrupted("trailing data".to_string()));
+        }
+
+        self.quirks = quirks;
+        self.unknown_opcode_policy = unknown_opcode_policy;
+        self.random = random;
+        self.memory = memory;
+        self.registers = registers;
+        self.address_register = address_register;
+        self.program_counter = program_counter;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.high_resolution = high_resolution;
+        self.selected_planes = selected_planes;
+        self.screen = screen;
+        self.keys = keys;
+        self.key_waiting_release = key_waiting_release;
+        self.halted = halted;
+        self.waiting_for_display_refresh = waiting_for_display_refresh;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = audio_pattern;
+        self.audio_pitch = audio_pitch;
+        self.instruction_count = instruction_count;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn new_chip8(rom : &[u8], quirks : Quirks) -> Result<Chip8, Chip8Error>
+    {
+        Chip8::new(rom, quirks, RandomGenerator::seeded(0))
+    }
+
+    #[test]
+    fn memory_size_is_validated()
+    {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.memory_size = 0x100;
+        assert_eq!(new_chip8(&[], quirks).err(), Some(Chip8Error::UnsupportedMemorySize { size : 0x100 }));
+        quirks.memory_size = 0x10001;
+        assert_eq!(new_chip8(&[], quirks).err(), Some(Chip8Error::UnsupportedMemorySize { size : 0x10001 }));
+        quirks.memory_size = 0x202;
+        assert!(new_chip8(&[0x00, 0xE0], quirks).is_ok());
+        assert_eq!(new_chip8(&[0x00, 0xE0, 0x00], quirks).err(), Some(Chip8Error::RomTooLarge { size : 3, max_size : 2 }));
+    }
+
+    #[test]
+    fn running_off_the_top_of_memory_is_an_error()
+    {
+        //6000 fills the whole 64K memory of XO-CHIP
+        let rom : Vec<u8> = [0x60, 0x00].iter().cloned().cycle().take(MAX_MEMORY_SIZE - 0x200).collect();
+        let mut chip8 = new_chip8(&rom, Quirks::xo_chip()).unwrap();
+        let result = chip8.run_frame(MAX_MEMORY_SIZE as u32);
+        assert_eq!(result, Err(Chip8Error::MemoryOutOfRange { program_counter : 0xFFFE, address : 0x10000 }));
+        assert_eq!(chip8.program_counter(), 0xFFFE);
+
+        //Skipping over the last instruction
+        let mut chip8 = new_chip8(&[], Quirks::xo_chip()).unwrap();
+        chip8.set_memory(0xFFFC, &[0x30, 0x00, 0x00, 0xE0]).unwrap();
+        chip8.set_program_counter(0xFFFC);
+        assert!(chip8.run_one_cycle().is_err());
+
+        //Calling from the last instruction
+        let mut chip8 = new_chip8(&[], Quirks::xo_chip()).unwrap();
+        chip8.set_memory(0xFFFE, &[0x22, 0x00]).unwrap();
+        chip8.set_program_counter(0xFFFE);
+        chip8.run_one_cycle().unwrap();
+        chip8.set_memory(0x200, &[0x00, 0xEE]).unwrap();
+        assert!(chip8.run_one_cycle().is_err());
+    }
+
+    #[test]
+    fn skips_jump_over_long_loads_only_in_xo_chip_mode()
+    {
+        //SE V0, 0 / LD I, LONG 0x1234
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+        let mut chip8 = new_chip8(&rom, Quirks::xo_chip()).unwrap();
+        chip8.run_one_cycle().unwrap();
+        assert_eq!(chip8.program_counter(), 0x206);
+
+        let mut chip8 = new_chip8(&rom, Quirks::super_chip()).unwrap();
+        chip8.run_one_cycle().unwrap();
+        assert_eq!(chip8.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn state_round_trip()
+    {
+        //LD V0, 0x12 / CALL 0x206 / JP 0x204 / LD V1, 0x34
+        let rom = [0x60, 0x12, 0x22, 0x06, 0x12, 0x04, 0x61, 0x34];
+        let mut chip8 = new_chip8(&rom, Quirks::super_chip()).unwrap();
+        chip8.run_one_cycle().unwrap();
+        chip8.run_one_cycle().unwrap();
+        chip8.set_keys(0x8001);
+        chip8.set_delay_timer(7);
+        let state = chip8.state();
+        assert_eq!(state.registers[0], 0x12);
+        assert_eq!(state.program_counter, 0x206);
+        assert_eq!(state.stack, [0x202]);
+        assert_eq!((state.delay_timer, state.keys), (7, 0x8001));
+        assert_eq!(state.opcode, Some(0x6134));
+        assert_eq!(state.instruction_count, 2);
+
+        let mut other = new_chip8(&rom, Quirks::super_chip()).unwrap();
+        other.set_state(&state).unwrap();
+        let restored = other.state();
+        //The instruction count is history, not state
+        assert_eq!(restored, State { instruction_count : 0, .. state });
+    }
+
+    #[test]
+    fn current_opcode_is_none_outside_of_memory()
+    {
+        let mut chip8 = new_chip8(&[0x61, 0x34], Quirks::cosmac_vip()).unwrap();
+        assert_eq!(chip8.current_opcode(), Some(0x6134));
+        chip8.set_program_counter(0xFFE);
+        assert_eq!(chip8.current_opcode(), Some(0x0000));
+        chip8.set_program_counter(0xFFF);
+        assert_eq!(chip8.current_opcode(), None);
+        assert_eq!(chip8.state().opcode, None);
+    }
+
+    #[test]
+    fn stacks_are_checked_before_anything_is_set()
+    {
+        let mut chip8 = new_chip8(&[], Quirks::cosmac_vip()).unwrap();
+        let before = chip8.state();
+        //The VIP has 12 entries
+        let mut state = State { registers : [1; 16], stack : vec![0x200; 13], .. before.clone() };
+        assert_eq!(chip8.set_state(&state), Err(Chip8Error::StackOverflow { program_counter : 0x200, depth : 12 }));
+        assert_eq!(chip8.state(), before);
+        state.stack = vec![0x200, 0xFFF];
+        assert_eq!(chip8.set_state(&state), Err(Chip8Error::MemoryOutOfRange { program_counter : 0x200, address : 0xFFF }));
+        assert_eq!(chip8.state(), before);
+        assert_eq!(chip8.set_stack(&[0x1000]), Err(Chip8Error::MemoryOutOfRange { program_counter : 0x200, address : 0x1000 }));
+        assert!(chip8.stack().is_empty());
+
+        state.stack = vec![0x200; 12];
+        chip8.set_state(&state).unwrap();
+        assert_eq!(chip8.stack().len(), 12);
+    }
+
+    #[test]
+    fn set_stack_rewrites_the_stack_in_memory()
+    {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.stack_in_memory = true;
+        //RET
+        let mut chip8 = new_chip8(&[0x00, 0xEE], quirks).unwrap();
+        chip8.set_stack(&[0x300, 0x400]).unwrap();
+        assert_eq!(chip8.stack(), [0x300, 0x400]);
+        assert_eq!(&chip8.memory()[0xECC .. 0xED0], [0x04, 0x02, 0x03, 0x02]);
+        chip8.run_one_cycle().unwrap();
+        assert_eq!(chip8.program_counter(), 0x402);
+        assert_eq!(chip8.stack(), [0x300]);
+    }
+
+    #[test]
+    fn set_memory_checks_the_whole_range()
+    {
+        let mut chip8 = new_chip8(&[], Quirks::cosmac_vip()).unwrap();
+        chip8.set_memory(0xFFE, &[1, 2]).unwrap();
+        assert_eq!(&chip8.memory()[0xFFE ..], [1, 2]);
+        chip8.set_memory(0x1000, &[]).unwrap();
+        assert_eq!(chip8.set_memory(0xFFE, &[3, 4, 5]), Err(Chip8Error::MemoryOutOfRange { program_counter : 0x200, address : 0x1000 }));
+        assert_eq!(chip8.set_memory(usize::MAX, &[3, 4]).err(), Some(Chip8Error::MemoryOutOfRange { program_counter : 0x200, address : usize::MAX }));
+        assert_eq!(&chip8.memory()[0xFFE ..], [1, 2]);
+    }
+}
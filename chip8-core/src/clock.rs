@@ -0,0 +1,72 @@
+/*
+Emulated time. The interpreter only knows about frames: run_frame executes a number of
+instructions then ticks the 60Hz timers. The host tells the clock how much real time went
+by and runs as many frames as it returns, so the core never reads the wall clock and
+tests can drive it frame by frame.
+A speed above 1 fast-forwards, below 1 plays in slow motion.
+*/
+
+use chip8::Chip8;
+use error::Chip8Error;
+
+pub const FRAMES_PER_SECOND : f64 = 60.0;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME : u32 = 10;
+
+//More frames than that for a single call means the host was suspended, they are dropped
+const MAX_FRAMES_PER_ADVANCE : u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clock
+{
+    pub instructions_per_frame : u32,
+    pub speed : f64,
+    pending_frames : f64,
+}
+
+impl Clock
+{
+    #[allow(dead_code)]
+    pub fn new(instructions_per_frame : u32) -> Clock
+    {
+        Clock
+        {
+            instructions_per_frame,
+            speed : 1.0,
+            pending_frames : 0.0,
+        }
+    }
+
+    //Number of frames to emulate for elapsed_seconds of host time
+    #[allow(dead_code)]
+    pub fn frames_for(&mut self, elapsed_seconds : f64) -> u32
+    {
+        self.pending_frames += elapsed_seconds * FRAMES_PER_SECOND * self.speed;
+        let frames = self.pending_frames.floor();
+        self.pending_frames -= frames;
+        if frames > MAX_FRAMES_PER_ADVANCE as f64 * self.speed.max(1.0)
+        {
+            return (MAX_FRAMES_PER_ADVANCE as f64 * self.speed.max(1.0)) as u32;
+        }
+        frames as u32
+    }
+
+    //Runs the frames due for elapsed_seconds of host time, returns how many were run
+    #[allow(dead_code)]
+    pub fn advance(&mut self, chip8 : &mut Chip8, elapsed_seconds : f64) -> Result<u32, Chip8Error>
+    {
+        let frames = self.frames_for(elapsed_seconds);
+        for _ in 0 .. frames
+        {
+            chip8.run_frame(self.instructions_per_frame)?;
+        }
+        Ok(frames)
+    }
+}
+
+impl Default for Clock
+{
+    fn default() -> Clock
+    {
+        Clock::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
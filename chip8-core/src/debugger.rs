@@ -0,0 +1,590 @@
+/*
+Debugger engine. It runs the interpreter one instruction at a time and ticks the timers
+itself every instructions_per_frame instructions, like Chip8::run_frame does, so that
+stepping through a program gives exactly the same execution as running it.
+Breakpoints stop before the instruction at their address runs, watchpoints stop right
+after the instruction that touched the watched memory.
+*/
+
+use std::fmt;
+use chip8::{Chip8, MemoryAccessKind};
+use clock::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use error::Chip8Error;
+use instruction::Instruction;
+
+//Instruction budget of the runs that have no natural end, like stepping over a call that never returns
+pub const DEFAULT_RUN_LIMIT : u64 = 10_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register
+{
+    V(u8),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register
+{
+    pub fn value(&self, chip8 : &Chip8) -> u16
+    {
+        match *self
+        {
+            Register::V(x) => chip8.registers()[x as usize] as u16,
+            Register::I => chip8.address_register(),
+            Register::DelayTimer => chip8.delay_timer() as u16,
+            Register::SoundTimer => chip8.sound_timer() as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison
+{
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl fmt::Display for Comparison
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        let symbol = match *self
+        {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition
+{
+    pub register : Register,
+    pub comparison : Comparison,
+    pub value : u16,
+}
+
+impl Condition
+{
+    pub fn is_met(&self, chip8 : &Chip8) -> bool
+    {
+        let value = self.register.value(chip8);
+        match self.comparison
+        {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} {} {:#X}", self.register, self.comparison, self.value)
+    }
+}
+
+//Without an address the condition is checked before every instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint
+{
+    pub address : Option<u16>,
+    pub condition : Option<Condition>,
+}
+
+impl Breakpoint
+{
+    fn is_hit(&self, chip8 : &Chip8) -> bool
+    {
+        if let Some(address) = self.address
+        {
+            if address != chip8.program_counter()
+            {
+                return false;
+            }
+        }
+        match self.condition
+        {
+            Some(condition) => condition.is_met(chip8),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match (self.address, self.condition)
+        {
+            (Some(address), Some(condition)) => write!(f, "{:#05X} if {}", address, condition),
+            (Some(address), None) => write!(f, "{:#05X}", address),
+            (None, Some(condition)) => write!(f, "if {}", condition),
+            (None, None) => write!(f, "always"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind
+{
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint
+{
+    pub address : u16,
+    pub length : u16,
+    pub kind : WatchKind,
+}
+
+impl Watchpoint
+{
+    fn matches(&self, kind : MemoryAccessKind, address : usize, length : usize) -> bool
+    {
+        let kind_matches = matches!((self.kind, kind),
+            (WatchKind::ReadWrite, _) | (WatchKind::Read, MemoryAccessKind::Read) | (WatchKind::Write, MemoryAccessKind::Write));
+        let start = self.address as usize;
+        let end = start + self.length as usize;
+        kind_matches && address < end && start < address + length
+    }
+}
+
+impl fmt::Display for Watchpoint
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        let kind = match self.kind
+        {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::ReadWrite => "read/write",
+        };
+        //A length of 0 is treated like 1, as matches does not stop on an empty range anyway
+        let end = self.address as usize + self.length.max(1) as usize - 1;
+        write!(f, "{} {:#05X}-{:#05X}", kind, self.address, end)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason
+{
+    //The requested step, step over, step out or frame was reached
+    Done,
+    //Index in breakpoints()
+    Breakpoint(usize),
+    //Index in watchpoints() and the access that triggered it
+    Watchpoint { index : usize, kind : MemoryAccessKind, address : usize },
+    Halted,
+    //The instruction budget of a run ran out
+    Limit,
+}
+
+pub struct Debugger
+{
+    pub instructions_per_frame : u32,
+    breakpoints : Vec<Breakpoint>,
+    watchpoints : Vec<Watchpoint>,
+    frame : u64,
+    instructions_in_frame : u32,
+}
+
+impl Debugger
+{
+    #[allow(dead_code)]
+    pub fn new(instructions_per_frame : u32) -> Debugger
+    {
+        Debugger
+        {
+            instructions_per_frame,
+            breakpoints : Vec::new(),
+            watchpoints : Vec::new(),
+            frame : 0,
+            instructions_in_frame : 0,
+        }
+    }
+
+    //Frames completed since the debugger took over
+    #[allow(dead_code)]
+    pub fn frame(&self) -> u64
+    {
+        self.frame
+    }
+
+    #[allow(dead_code)]
+    pub fn breakpoints(&self) -> &[Breakpoint]
+    {
+        &self.breakpoints
+    }
+
+    #[allow(dead_code)]
+    pub fn watchpoints(&self) -> &[Watchpoint]
+    {
+        &self.watchpoints
+    }
+
+    #[allow(dead_code)]
+    pub fn add_breakpoint(&mut self, breakpoint : Breakpoint) -> usize
+    {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_breakpoint(&mut self, index : usize) -> Option<Breakpoint>
+    {
+        if index < self.breakpoints.len() { Some(self.breakpoints.remove(index)) } else { None }
+    }
+
+    #[allow(dead_code)]
+    pub fn clear_breakpoints(&mut self)
+    {
+        self.breakpoints.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn add_watchpoint(&mut self, watchpoint : Watchpoint) -> usize
+    {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_watchpoint(&mut self, index : usize) -> Option<Watchpoint>
+    {
+        if index < self.watchpoints.len() { Some(self.watchpoints.remove(index)) } else { None }
+    }
+
+    //Executes exactly one instruction, breakpoints are ignored
+    #[allow(dead_code)]
+    pub fn step(&mut self, chip8 : &mut Chip8) -> Result<StopReason, Chip8Error>
+    {
+        self.run_until(chip8, Some(1), |_| false)
+    }
+
+    //Like step but runs a whole subroutine when the instruction is a 2NNN, at most limit instructions
+    #[allow(dead_code)]
+    pub fn step_over(&mut self, chip8 : &mut Chip8, limit : Option<u64>) -> Result<StopReason, Chip8Error>
+    {
+        match chip8.current_instruction()
+        {
+            Some(Instruction::Call(_)) =>
+            {
+                let depth = chip8.stack().len() + 1;
+                match self.step(chip8)?
+                {
+                    StopReason::Done => self.run_to_return(chip8, depth, limit.map(|limit| limit.saturating_sub(1))),
+                    reason => Ok(reason),
+                }
+            }
+            _ => self.step(chip8),
+        }
+    }
+
+    //Runs until the current subroutine returns with 00EE, at most limit instructions
+    #[allow(dead_code)]
+    pub fn step_out(&mut self, chip8 : &mut Chip8, limit : Option<u64>) -> Result<StopReason, Chip8Error>
+    {
+        let depth = chip8.stack().len();
+        if depth == 0
+        {
+            return self.resume(chip8, limit);
+        }
+        self.run_to_return(chip8, depth, limit)
+    }
+
+    /*
+    Runs until the stack holds less than depth return addresses, at most limit instructions.
+    Stepping over or out of a subroutine in several runs goes through this with the depth of
+    the first one.
+    */
+    #[allow(dead_code)]
+    pub fn run_to_return(&mut self, chip8 : &mut Chip8, depth : usize, limit : Option<u64>) -> Result<StopReason, Chip8Error>
+    {
+        let returned = move |chip8 : &Chip8| chip8.stack().len() < depth;
+        if returned(chip8)
+        {
+            return Ok(StopReason::Done);
+        }
+        match self.run_until(chip8, limit, returned)?
+        {
+            StopReason::Done if !returned(chip8) => Ok(StopReason::Limit),
+            reason => Ok(reason),
+        }
+    }
+
+    //Runs until frame() reaches the given frame
+    #[allow(dead_code)]
+    pub fn run_to_frame(&mut self, chip8 : &mut Chip8, frame : u64) -> Result<StopReason, Chip8Error>
+    {
+        if self.frame >= frame
+        {
+            return Ok(StopReason::Done);
+        }
+        while self.frame < frame
+        {
+            match self.run_until(chip8, Some(1), |_| false)?
+            {
+                StopReason::Done => (),
+                StopReason::Halted =>
+                {
+                    //A halted interpreter still has its timers ticking
+                    self.end_frame(chip8);
+                }
+                reason => return Ok(reason),
+            }
+        }
+        Ok(StopReason::Done)
+    }
+
+    //Runs until a breakpoint or watchpoint is hit, or limit instructions were executed
+    #[allow(dead_code)]
+    pub fn resume(&mut self, chip8 : &mut Chip8, limit : Option<u64>) -> Result<StopReason, Chip8Error>
+    {
+        match self.run_until(chip8, limit, |_| false)?
+        {
+            StopReason::Done => Ok(StopReason::Limit),
+            reason => Ok(reason),
+        }
+    }
+
+    /*
+    Executes instructions until stop returns true after one of them, a breakpoint or a
+    watchpoint is hit, or limit instructions were executed.
+    A breakpoint at the starting instruction is not reported so that resuming from a
+    breakpoint does not stop right away.
+    */
+    fn run_until<F>(&mut self, chip8 : &mut Chip8, limit : Option<u64>, stop : F) -> Result<StopReason, Chip8Error> where F : Fn(&Chip8) -> bool
+    {
+        let mut executed = 0;
+        loop
+        {
+            if chip8.is_halted()
+            {
+                return Ok(StopReason::Halted);
+            }
+            if executed > 0
+            {
+                if let Some(index) = self.first_breakpoint_hit(chip8)
+                {
+                    return Ok(StopReason::Breakpoint(index));
+                }
+            }
+            if limit.is_some_and(|limit| executed >= limit)
+            {
+                return Ok(StopReason::Done);
+            }
+
+            let access = chip8.next_memory_access();
+            self.execute(chip8)?;
+            executed += 1;
+
+            if let Some(access) = access
+            {
+                if let Some(index) = self.watchpoints.iter().position(|watchpoint| watchpoint.matches(access.kind, access.address, access.length))
+                {
+                    return Ok(StopReason::Watchpoint { index, kind : access.kind, address : access.address });
+                }
+            }
+            if stop(chip8)
+            {
+                return Ok(StopReason::Done);
+            }
+        }
+    }
+
+    fn first_breakpoint_hit(&self, chip8 : &Chip8) -> Option<usize>
+    {
+        self.breakpoints.iter().position(|breakpoint| breakpoint.is_hit(chip8))
+    }
+
+    //One instruction, followed by the end of the frame when it is due
+    fn execute(&mut self, chip8 : &mut Chip8) -> Result<(), Chip8Error>
+    {
+        chip8.run_one_cycle()?;
+        self.instructions_in_frame += 1;
+        //Same conditions as the end of Chip8::run_frame
+        if self.instructions_in_frame >= self.instructions_per_frame || chip8.is_waiting_for_display_refresh() || chip8.is_halted()
+        {
+            self.end_frame(chip8);
+        }
+        Ok(())
+    }
+
+    fn end_frame(&mut self, chip8 : &mut Chip8)
+    {
+        chip8.tick_timers();
+        self.frame += 1;
+        self.instructions_in_frame = 0;
+    }
+}
+
+impl Default for Debugger
+{
+    fn default() -> Debugger
+    {
+        Debugger::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use quirks::Quirks;
+    use random::RandomGenerator;
+
+    //Calls the subroutine at 0x204, which returns unless V0 is 0 on entry, then loops forever
+    const ROM : [u8; 10] = [0x22, 0x04, 0x12, 0x02, 0x30, 0x00, 0x00, 0xEE, 0x12, 0x08];
+
+    fn new_chip8(rom : &[u8]) -> Chip8
+    {
+        Chip8::new(rom, Quirks::cosmac_vip(), RandomGenerator::seeded(0)).unwrap()
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call()
+    {
+        let mut chip8 = new_chip8(&ROM);
+        chip8.set_register(0, 1);
+        let mut debugger = Debugger::default();
+        assert_eq!(debugger.step_over(&mut chip8, None), Ok(StopReason::Done));
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert!(chip8.stack().is_empty());
+    }
+
+    #[test]
+    fn step_over_a_call_that_never_returns_stops_at_the_limit()
+    {
+        let mut chip8 = new_chip8(&ROM);
+        let mut debugger = Debugger::default();
+        assert_eq!(debugger.step_over(&mut chip8, Some(100)), Ok(StopReason::Limit));
+        assert_eq!(chip8.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn step_out_stops_at_the_limit()
+    {
+        let mut chip8 = new_chip8(&ROM);
+        let mut debugger = Debugger::default();
+        assert_eq!(debugger.step(&mut chip8), Ok(StopReason::Done));
+        assert_eq!(debugger.step_out(&mut chip8, Some(100)), Ok(StopReason::Limit));
+
+        let mut chip8 = new_chip8(&ROM);
+        chip8.set_register(0, 1);
+        assert_eq!(debugger.step(&mut chip8), Ok(StopReason::Done));
+        assert_eq!(debugger.step_out(&mut chip8, Some(100)), Ok(StopReason::Done));
+        assert_eq!(chip8.program_counter(), 0x202);
+
+        //Outside of a subroutine it is a plain run
+        assert_eq!(debugger.step_out(&mut chip8, Some(100)), Ok(StopReason::Limit));
+    }
+
+    #[test]
+    fn empty_watchpoints_display()
+    {
+        let watchpoint = Watchpoint { address : 0, length : 0, kind : WatchKind::Write };
+        assert_eq!(watchpoint.to_string(), "write 0x000-0x000");
+        let watchpoint = Watchpoint { address : 0x300, length : 2, kind : WatchKind::Read };
+        assert_eq!(watchpoint.to_string(), "read 0x300-0x301");
+    }
+
+    //ADD V0, 1 then JP 0x200
+    const COUNTER : [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn breakpoints_stop_before_their_address()
+    {
+        let mut chip8 = new_chip8(&COUNTER);
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(Breakpoint { address : Some(0x200), condition : None });
+        //Not on the instruction the run starts from
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Breakpoint(0)));
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert_eq!(chip8.registers()[0], 1);
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Breakpoint(0)));
+        assert_eq!(chip8.registers()[0], 2);
+    }
+
+    #[test]
+    fn conditional_breakpoints()
+    {
+        let mut chip8 = new_chip8(&COUNTER);
+        let mut debugger = Debugger::default();
+        let condition = Condition { register : Register::V(0), comparison : Comparison::Equal, value : 5 };
+        debugger.add_breakpoint(Breakpoint { address : None, condition : Some(condition) });
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Breakpoint(0)));
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x202, 5));
+
+        let mut chip8 = new_chip8(&COUNTER);
+        let mut debugger = Debugger::default();
+        let condition = Condition { register : Register::V(0), comparison : Comparison::GreaterOrEqual, value : 3 };
+        debugger.add_breakpoint(Breakpoint { address : Some(0x200), condition : Some(condition) });
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Breakpoint(0)));
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x200, 3));
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access()
+    {
+        //LD I, 0x300 / LD B, V1 / LD V0, [I] / JP 0x206
+        let rom = [0xA3, 0x00, 0xF1, 0x33, 0xF0, 0x65, 0x12, 0x06];
+        let mut chip8 = new_chip8(&rom);
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint { address : 0x301, length : 1, kind : WatchKind::Write });
+        debugger.add_watchpoint(Watchpoint { address : 0x300, length : 1, kind : WatchKind::Read });
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Watchpoint { index : 0, kind : MemoryAccessKind::Write, address : 0x300 }));
+        assert_eq!(chip8.program_counter(), 0x204);
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Watchpoint { index : 1, kind : MemoryAccessKind::Read, address : 0x300 }));
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Limit));
+    }
+
+    #[test]
+    fn watchpoints_see_the_stack_in_memory()
+    {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.stack_in_memory = true;
+        //CALL 0x206 / JP 0x202 / RET
+        let rom = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x00, 0xEE];
+        let mut chip8 = Chip8::new(&rom, quirks, RandomGenerator::seeded(0)).unwrap();
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint { address : 0xECE, length : 2, kind : WatchKind::ReadWrite });
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Watchpoint { index : 0, kind : MemoryAccessKind::Write, address : 0xECE }));
+        assert_eq!(chip8.read_byte(0xECF), Ok(0x02));
+        assert_eq!(debugger.resume(&mut chip8, Some(1000)), Ok(StopReason::Watchpoint { index : 0, kind : MemoryAccessKind::Read, address : 0xECE }));
+        assert_eq!(chip8.program_counter(), 0x202);
+    }
+}
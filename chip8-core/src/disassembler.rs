@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet};
+use instruction::Instruction;
+
+const ROM_BASE_ADDRESS : usize = 0x200;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind
+{
+    Subroutine,
+    Jump,
+    Data,
+}
+
+/*
+Disassemble a rom loaded at 0x200. Code is told apart from data by following the control
+flow from the entry point through jumps, calls and skips, everything else is dumped as
+bytes with a bitmap since most data in a CHIP-8 rom are sprites.
+BNNN targets depend on a register value and cannot be followed.
+*/
+#[allow(dead_code)]
+pub fn disassemble(rom : &[u8]) -> String
+{
+    let (code, labels) = trace_code(rom);
+    let label_name = |address : u16| -> String
+    {
+        match labels.get(&address)
+        {
+            Some(kind) => format_label(address, *kind),
+            None => format!("{:#05X}", address),
+        }
+    };
+
+    let mut output = String::new();
+    let mut defined_labels = BTreeSet::new();
+    let mut address = ROM_BASE_ADDRESS;
+    let end = ROM_BASE_ADDRESS + rom.len();
+    while address < end
+    {
+        if let Some(kind) = labels.get(&(address as u16))
+        {
+            output.push_str(&format!("\n{}:\n", format_label(address as u16, *kind)));
+            defined_labels.insert(address as u16);
+        }
+
+        if code.contains(&address)
+        {
+            let opcode = read_word(rom, address).unwrap();
+            let instruction = Instruction::decode(opcode);
+            if instruction == Instruction::LdILong
+            {
+                let long_address = read_word(rom, address + 2).unwrap();
+                output.push_str(&format!("    {:<28}; {:03X}: {:04X} {:04X}\n", format!("LD I, LONG {}", label_name(long_address)), address, opcode, long_address));
+            }
+            else
+            {
+                output.push_str(&format!("    {:<28}; {:03X}: {:04X}\n", instruction.format_with_addresses(label_name), address, opcode));
+            }
+            address += instruction.size() as usize;
+        }
+        else
+        {
+            let byte = rom[address - ROM_BASE_ADDRESS];
+            output.push_str(&format!("    {:<28}; {:03X}: {}\n", format!("DB {:#04X}", byte), address, format_sprite_row(byte)));
+            address += 1;
+        }
+    }
+
+    //Targets in the middle of an instruction never start a line, they become constants instead
+    let mut header = format!("; {} bytes, {} instructions found\n", rom.len(), code.len());
+    for (&address, &kind) in labels.iter().filter(|&(address, _)| !defined_labels.contains(address))
+    {
+        header.push_str(&format!("{} EQU {:#05X}\n", format_label(address, kind), address));
+    }
+    header + &output
+}
+
+//Instructions reachable from the entry point, in address order
+#[allow(dead_code)]
+pub fn reachable_instructions(rom : &[u8]) -> Vec<(u16, Instruction)>
+{
+    let (code, _) = trace_code(rom);
+    code.iter().filter_map(|&address| read_word(rom, address).map(|opcode| (address as u16, Instruction::decode(opcode)))).collect()
+}
+
+//Returns the addresses of every reachable instruction along with the labels to generate
+fn trace_code(rom : &[u8]) -> (BTreeSet<usize>, BTreeMap<u16, LabelKind>)
+{
+    let mut code = BTreeSet::new();
+    let mut labels = BTreeMap::new();
+    let mut to_visit = vec![ROM_BASE_ADDRESS];
+
+    while let Some(address) = to_visit.pop()
+    {
+        if code.contains(&address)
+        {
+            continue;
+        }
+        let opcode = match read_word(rom, address)
+        {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        let instruction = Instruction::decode(opcode);
+        if instruction == Instruction::LdILong && read_word(rom, address + 2).is_none()
+        {
+            continue;
+        }
+        code.insert(address);
+
+        let next = address + instruction.size() as usize;
+        match instruction
+        {
+            Instruction::Jp(nnn) =>
+            {
+                add_label(&mut labels, nnn, LabelKind::Jump);
+                to_visit.push(nnn as usize);
+            }
+            Instruction::Call(nnn) =>
+            {
+                add_label(&mut labels, nnn, LabelKind::Subroutine);
+                to_visit.push(nnn as usize);
+                to_visit.push(next);
+            }
+            Instruction::SeByte { .. } | Instruction::SneByte { .. } |
+            Instruction::SeRegister { .. } | Instruction::SneRegister { .. } |
+            Instruction::Skp(_) | Instruction::Sknp(_) =>
+            {
+                to_visit.push(next);
+                let skipped_size = match read_word(rom, next)
+                {
+                    Some(opcode) => Instruction::decode(opcode).size() as usize,
+                    None => 2,
+                };
+                to_visit.push(next + skipped_size);
+            }
+            Instruction::LdI(nnn) =>
+            {
+                add_label(&mut labels, nnn, LabelKind::Data);
+                to_visit.push(next);
+            }
+            Instruction::LdILong =>
+            {
+                let long_address = read_word(rom, address + 2).unwrap();
+                add_label(&mut labels, long_address, LabelKind::Data);
+                to_visit.push(next);
+            }
+            Instruction::JpOffset(nnn) =>
+            {
+                add_label(&mut labels, nnn, LabelKind::Jump);
+            }
+            Instruction::Ret | Instruction::Exit | Instruction::Unknown(_) => {}
+            _ =>
+            {
+                to_visit.push(next);
+            }
+        }
+    }
+
+    //Only keep labels that point inside the rom
+    let end = ROM_BASE_ADDRESS + rom.len();
+    let labels = labels.into_iter().filter(|&(address, _)| (address as usize) >= ROM_BASE_ADDRESS && (address as usize) < end).collect();
+    (code, labels)
+}
+
+//A data label is upgraded if the same address is also a jump or call target
+fn add_label(labels : &mut BTreeMap<u16, LabelKind>, address : u16, kind : LabelKind)
+{
+    let entry = labels.entry(address).or_insert(kind);
+    if kind < *entry
+    {
+        *entry = kind;
+    }
+}
+
+fn format_label(address : u16, kind : LabelKind) -> String
+{
+    match kind
+    {
+        LabelKind::Subroutine => format!("sub_{:03X}", address),
+        LabelKind::Jump => format!("label_{:03X}", address),
+        LabelKind::Data => format!("data_{:03X}", address),
+    }
+}
+
+fn format_sprite_row(byte : u8) -> String
+{
+    (0 .. 8).map(|i| if byte & (0x80 >> i) != 0 { '#' } else { '.' }).collect()
+}
+
+fn read_word(rom : &[u8], address : usize) -> Option<u16>
+{
+    if address < ROM_BASE_ADDRESS || address + 1 >= ROM_BASE_ADDRESS + rom.len()
+    {
+        return None;
+    }
+    let offset = address - ROM_BASE_ADDRESS;
+    Some((rom[offset] as u16) << 8 | rom[offset + 1] as u16)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use assembler::assemble;
+    use std::path::Path;
+
+    #[test]
+    fn targets_inside_instructions_reassemble()
+    {
+        //LD I, 0x203 points at the low byte of itself
+        let rom = [0xA2, 0x03, 0x12, 0x00];
+        let source = disassemble(&rom);
+        assert!(source.contains("data_203 EQU 0x203"), "{}", source);
+        assert_eq!(assemble(&source, Path::new(".")).unwrap(), rom.to_vec());
+    }
+
+    #[test]
+    fn jumps_into_the_middle_of_an_instruction_reassemble()
+    {
+        //JP 0x201 lands on the second byte of the jump itself
+        let rom = [0x12, 0x01, 0xFD];
+        let source = disassemble(&rom);
+        assert!(source.contains("label_201 EQU 0x201"), "{}", source);
+        assert_eq!(assemble(&source, Path::new(".")).unwrap(), rom.to_vec());
+    }
+
+    #[test]
+    fn odd_code_addresses_get_labels()
+    {
+        //Code at 0x205 behind three bytes of data
+        let rom = [0x12, 0x05, 0x00, 0x00, 0xFF, 0x00, 0xE0, 0x12, 0x05];
+        let source = disassemble(&rom);
+        assert!(source.contains("\nlabel_205:\n"), "{}", source);
+        assert_eq!(assemble(&source, Path::new(".")).unwrap(), rom.to_vec());
+    }
+}
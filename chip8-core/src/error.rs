@@ -0,0 +1,55 @@
+use std::error;
+use std::fmt;
+use quirks::{MIN_MEMORY_SIZE, MAX_MEMORY_SIZE};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error
+{
+    //00EE executed with an empty stack
+    StackUnderflow { program_counter : u16 },
+    //2NNN executed with a full stack
+    StackOverflow { program_counter : u16, depth : usize },
+    MemoryOutOfRange { program_counter : u16, address : usize },
+    RomTooLarge { size : usize, max_size : usize },
+    //Quirks::memory_size outside of MIN_MEMORY_SIZE ..= MAX_MEMORY_SIZE
+    UnsupportedMemorySize { size : usize },
+    UnknownOpcode { program_counter : u16, opcode : u16 },
+}
+
+impl fmt::Display for Chip8Error
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Chip8Error::StackUnderflow { program_counter } =>
+                write!(f, "stack underflow: return with an empty stack at {:#06X}", program_counter),
+            Chip8Error::StackOverflow { program_counter, depth } =>
+                write!(f, "stack overflow: call nested deeper than {} levels at {:#06X}", depth, program_counter),
+            Chip8Error::MemoryOutOfRange { program_counter, address } =>
+                write!(f, "memory access out of range at address {:#06X} by instruction at {:#06X}", address, program_counter),
+            Chip8Error::RomTooLarge { size, max_size } =>
+                write!(f, "rom is {} bytes but at most {} bytes fit in memory", size, max_size),
+            Chip8Error::UnsupportedMemorySize { size } =>
+                write!(f, "memory size {} is not supported, it must be between {:#X} and {:#X} bytes", size, MIN_MEMORY_SIZE, MAX_MEMORY_SIZE),
+            Chip8Error::UnknownOpcode { program_counter, opcode } =>
+                write!(f, "unknown opcode {:#06X} at {:#06X}", opcode, program_counter),
+        }
+    }
+}
+
+impl error::Error for Chip8Error
+{
+}
+
+//What the interpreter does when it meets an opcode it does not know
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownOpcodePolicy
+{
+    //Ignore it and continue with the next instruction
+    Skip,
+    //Stop executing instructions, like 00FD does
+    Halt,
+    //Report a Chip8Error::UnknownOpcode
+    Error,
+}
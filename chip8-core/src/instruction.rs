@@ -0,0 +1,437 @@
+/*
+Decoded form of an opcode. Mnemonics follow Cowgod's technical reference
+(http://devernay.free.fr/hacks/chip8/C8TECH10.HTM) extended with the SUPER-CHIP and
+XO-CHIP instructions. Register operands are register indexes, not their values.
+*/
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction
+{
+    //00E0
+    Cls,
+    //00EE
+    Ret,
+    //0NNN, machine code routine of the original interpreter
+    Sys(u16),
+    //00CN
+    ScrollDown(u8),
+    //00FB
+    ScrollRight,
+    //00FC
+    ScrollLeft,
+    //00FD
+    Exit,
+    //00FE
+    LowResolution,
+    //00FF
+    HighResolution,
+    //1NNN
+    Jp(u16),
+    //2NNN
+    Call(u16),
+    //3XNN
+    SeByte { x : u8, byte : u8 },
+    //4XNN
+    SneByte { x : u8, byte : u8 },
+    //5XY0
+    SeRegister { x : u8, y : u8 },
+    //5XY2
+    SaveRange { x : u8, y : u8 },
+    //5XY3
+    LoadRange { x : u8, y : u8 },
+    //6XNN
+    LdByte { x : u8, byte : u8 },
+    //7XNN
+    AddByte { x : u8, byte : u8 },
+    //8XY0
+    LdRegister { x : u8, y : u8 },
+    //8XY1
+    Or { x : u8, y : u8 },
+    //8XY2
+    And { x : u8, y : u8 },
+    //8XY3
+    Xor { x : u8, y : u8 },
+    //8XY4
+    AddRegister { x : u8, y : u8 },
+    //8XY5
+    Sub { x : u8, y : u8 },
+    //8XY6
+    Shr { x : u8, y : u8 },
+    //8XY7
+    Subn { x : u8, y : u8 },
+    //8XYE
+    Shl { x : u8, y : u8 },
+    //9XY0
+    SneRegister { x : u8, y : u8 },
+    //ANNN
+    LdI(u16),
+    //BNNN
+    JpOffset(u16),
+    //CXNN
+    Rnd { x : u8, byte : u8 },
+    //DXYN
+    Drw { x : u8, y : u8, n : u8 },
+    //EX9E
+    Skp(u8),
+    //EXA1
+    Sknp(u8),
+    //F000 NNNN, the address is stored in the word following the opcode
+    LdILong,
+    //FN01
+    Plane(u8),
+    //F002
+    Audio,
+    //FX07
+    LdFromDelayTimer(u8),
+    //FX0A
+    WaitKey(u8),
+    //FX15
+    LdDelayTimer(u8),
+    //FX18
+    LdSoundTimer(u8),
+    //FX1E
+    AddI(u8),
+    //FX29
+    LdFont(u8),
+    //FX30
+    LdBigFont(u8),
+    //FX33
+    Bcd(u8),
+    //FX3A
+    Pitch(u8),
+    //FX55
+    Store(u8),
+    //FX65
+    Load(u8),
+    //FX75
+    StoreFlags(u8),
+    //FX85
+    LoadFlags(u8),
+    Unknown(u16),
+}
+
+impl Instruction
+{
+    pub fn decode(opcode : u16) -> Instruction
+    {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode & 0xF000
+        {
+            0x0000 => match opcode
+            {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowResolution,
+                0x00FF => Instruction::HighResolution,
+                _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown(n),
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jp(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SeByte { x, byte : nn },
+            0x4000 => Instruction::SneByte { x, byte : nn },
+            0x5000 => match n
+            {
+                0x0 => Instruction::SeRegister { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => Instruction::Unknown(opcode),
+            },
+            0x6000 => Instruction::LdByte { x, byte : nn },
+            0x7000 => Instruction::AddByte { x, byte : nn },
+            0x8000 => match n
+            {
+                0x0 => Instruction::LdRegister { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddRegister { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::Shr { x, y },
+                0x7 => Instruction::Subn { x, y },
+                0xE => Instruction::Shl { x, y },
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9000 if n == 0 => Instruction::SneRegister { x, y },
+            0xA000 => Instruction::LdI(nnn),
+            0xB000 => Instruction::JpOffset(nnn),
+            0xC000 => Instruction::Rnd { x, byte : nn },
+            0xD000 => Instruction::Drw { x, y, n },
+            0xE000 => match nn
+            {
+                0x9E => Instruction::Skp(x),
+                0xA1 => Instruction::Sknp(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF000 => match nn
+            {
+                0x00 if x == 0 => Instruction::LdILong,
+                0x01 => Instruction::Plane(x),
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::LdFromDelayTimer(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::LdDelayTimer(x),
+                0x18 => Instruction::LdSoundTimer(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LdFont(x),
+                0x30 => Instruction::LdBigFont(x),
+                0x33 => Instruction::Bcd(x),
+                0x3A => Instruction::Pitch(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                0x75 => Instruction::StoreFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    pub fn encode(&self) -> u16
+    {
+        fn xnn(base : u16, x : u8, nn : u8) -> u16
+        {
+            base | ((x as u16 & 0xF) << 8) | nn as u16
+        }
+
+        fn xyn(base : u16, x : u8, y : u8, n : u8) -> u16
+        {
+            base | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n as u16 & 0xF)
+        }
+
+        match *self
+        {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Sys(nnn) => nnn & 0x0FFF,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowResolution => 0x00FE,
+            Instruction::HighResolution => 0x00FF,
+            Instruction::Jp(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SeByte { x, byte } => xnn(0x3000, x, byte),
+            Instruction::SneByte { x, byte } => xnn(0x4000, x, byte),
+            Instruction::SeRegister { x, y } => xyn(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xyn(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xyn(0x5000, x, y, 0x3),
+            Instruction::LdByte { x, byte } => xnn(0x6000, x, byte),
+            Instruction::AddByte { x, byte } => xnn(0x7000, x, byte),
+            Instruction::LdRegister { x, y } => xyn(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xyn(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xyn(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xyn(0x8000, x, y, 0x3),
+            Instruction::AddRegister { x, y } => xyn(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xyn(0x8000, x, y, 0x5),
+            Instruction::Shr { x, y } => xyn(0x8000, x, y, 0x6),
+            Instruction::Subn { x, y } => xyn(0x8000, x, y, 0x7),
+            Instruction::Shl { x, y } => xyn(0x8000, x, y, 0xE),
+            Instruction::SneRegister { x, y } => xyn(0x9000, x, y, 0x0),
+            Instruction::LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JpOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Rnd { x, byte } => xnn(0xC000, x, byte),
+            Instruction::Drw { x, y, n } => xyn(0xD000, x, y, n),
+            Instruction::Skp(x) => xnn(0xE000, x, 0x9E),
+            Instruction::Sknp(x) => xnn(0xE000, x, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => xnn(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdFromDelayTimer(x) => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey(x) => xnn(0xF000, x, 0x0A),
+            Instruction::LdDelayTimer(x) => xnn(0xF000, x, 0x15),
+            Instruction::LdSoundTimer(x) => xnn(0xF000, x, 0x18),
+            Instruction::AddI(x) => xnn(0xF000, x, 0x1E),
+            Instruction::LdFont(x) => xnn(0xF000, x, 0x29),
+            Instruction::LdBigFont(x) => xnn(0xF000, x, 0x30),
+            Instruction::Bcd(x) => xnn(0xF000, x, 0x33),
+            Instruction::Pitch(x) => xnn(0xF000, x, 0x3A),
+            Instruction::Store(x) => xnn(0xF000, x, 0x55),
+            Instruction::Load(x) => xnn(0xF000, x, 0x65),
+            Instruction::StoreFlags(x) => xnn(0xF000, x, 0x75),
+            Instruction::LoadFlags(x) => xnn(0xF000, x, 0x85),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+
+    //Size in bytes, F000 NNNN is the only instruction longer than 2 bytes
+    pub fn size(&self) -> u16
+    {
+        match *self
+        {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    //Mnemonic of the instruction, format_address renders the NNN operands so that callers
+    //like the disassembler can replace them with labels
+    pub fn format_with_addresses<F>(&self, format_address : F) -> String where F : Fn(u16) -> String
+    {
+        match *self
+        {
+            Instruction::Cls => "CLS".to_string(),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::Sys(nnn) => format!("SYS {}", format_address(nnn)),
+            Instruction::ScrollDown(n) => format!("SCD {}", n),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowResolution => "LOW".to_string(),
+            Instruction::HighResolution => "HIGH".to_string(),
+            Instruction::Jp(nnn) => format!("JP {}", format_address(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", format_address(nnn)),
+            Instruction::SeByte { x, byte } => format!("SE V{:X}, {:#04X}", x, byte),
+            Instruction::SneByte { x, byte } => format!("SNE V{:X}, {:#04X}", x, byte),
+            Instruction::SeRegister { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+            Instruction::LdByte { x, byte } => format!("LD V{:X}, {:#04X}", x, byte),
+            Instruction::AddByte { x, byte } => format!("ADD V{:X}, {:#04X}", x, byte),
+            Instruction::LdRegister { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegister { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::SneRegister { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => format!("LD I, {}", format_address(nnn)),
+            Instruction::JpOffset(nnn) => format!("JP V0, {}", format_address(nnn)),
+            Instruction::Rnd { x, byte } => format!("RND V{:X}, {:#04X}", x, byte),
+            Instruction::Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => format!("SKP V{:X}", x),
+            Instruction::Sknp(x) => format!("SKNP V{:X}", x),
+            Instruction::LdILong => "LD I, LONG".to_string(),
+            Instruction::Plane(n) => format!("PLANE {}", n),
+            Instruction::Audio => "AUDIO".to_string(),
+            Instruction::LdFromDelayTimer(x) => format!("LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => format!("LD V{:X}, K", x),
+            Instruction::LdDelayTimer(x) => format!("LD DT, V{:X}", x),
+            Instruction::LdSoundTimer(x) => format!("LD ST, V{:X}", x),
+            Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+            Instruction::LdFont(x) => format!("LD F, V{:X}", x),
+            Instruction::LdBigFont(x) => format!("LD HF, V{:X}", x),
+            Instruction::Bcd(x) => format!("LD B, V{:X}", x),
+            Instruction::Pitch(x) => format!("PITCH V{:X}", x),
+            Instruction::Store(x) => format!("LD [I], V{:X}", x),
+            Instruction::Load(x) => format!("LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => format!("LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => format!("LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => format!("DW {:#06X}", opcode),
+        }
+    }
+}
+
+impl fmt::Display for Instruction
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}", self.format_with_addresses(|address| format!("{:#05X}", address)))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    //One instance of every variant, with operands that differ from each other
+    fn all_instructions() -> Vec<(u16, Instruction)>
+    {
+        vec![
+            (0x00E0, Instruction::Cls),
+            (0x00EE, Instruction::Ret),
+            (0x0123, Instruction::Sys(0x123)),
+            (0x00C5, Instruction::ScrollDown(5)),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FD, Instruction::Exit),
+            (0x00FE, Instruction::LowResolution),
+            (0x00FF, Instruction::HighResolution),
+            (0x1234, Instruction::Jp(0x234)),
+            (0x2345, Instruction::Call(0x345)),
+            (0x3A12, Instruction::SeByte { x : 0xA, byte : 0x12 }),
+            (0x4B34, Instruction::SneByte { x : 0xB, byte : 0x34 }),
+            (0x5120, Instruction::SeRegister { x : 1, y : 2 }),
+            (0x5342, Instruction::SaveRange { x : 3, y : 4 }),
+            (0x5563, Instruction::LoadRange { x : 5, y : 6 }),
+            (0x6C56, Instruction::LdByte { x : 0xC, byte : 0x56 }),
+            (0x7D78, Instruction::AddByte { x : 0xD, byte : 0x78 }),
+            (0x8120, Instruction::LdRegister { x : 1, y : 2 }),
+            (0x8231, Instruction::Or { x : 2, y : 3 }),
+            (0x8342, Instruction::And { x : 3, y : 4 }),
+            (0x8453, Instruction::Xor { x : 4, y : 5 }),
+            (0x8564, Instruction::AddRegister { x : 5, y : 6 }),
+            (0x8675, Instruction::Sub { x : 6, y : 7 }),
+            (0x8786, Instruction::Shr { x : 7, y : 8 }),
+            (0x8897, Instruction::Subn { x : 8, y : 9 }),
+            (0x89AE, Instruction::Shl { x : 9, y : 0xA }),
+            (0x9EF0, Instruction::SneRegister { x : 0xE, y : 0xF }),
+            (0xA456, Instruction::LdI(0x456)),
+            (0xB567, Instruction::JpOffset(0x567)),
+            (0xCE9A, Instruction::Rnd { x : 0xE, byte : 0x9A }),
+            (0xD12F, Instruction::Drw { x : 1, y : 2, n : 0xF }),
+            (0xE39E, Instruction::Skp(3)),
+            (0xE4A1, Instruction::Sknp(4)),
+            (0xF000, Instruction::LdILong),
+            (0xF201, Instruction::Plane(2)),
+            (0xF002, Instruction::Audio),
+            (0xF507, Instruction::LdFromDelayTimer(5)),
+            (0xF60A, Instruction::WaitKey(6)),
+            (0xF715, Instruction::LdDelayTimer(7)),
+            (0xF818, Instruction::LdSoundTimer(8)),
+            (0xF91E, Instruction::AddI(9)),
+            (0xFA29, Instruction::LdFont(0xA)),
+            (0xFB30, Instruction::LdBigFont(0xB)),
+            (0xFC33, Instruction::Bcd(0xC)),
+            (0xFD3A, Instruction::Pitch(0xD)),
+            (0xFE55, Instruction::Store(0xE)),
+            (0xFF65, Instruction::Load(0xF)),
+            (0xF175, Instruction::StoreFlags(1)),
+            (0xF285, Instruction::LoadFlags(2)),
+            (0x5121, Instruction::Unknown(0x5121)),
+        ]
+    }
+
+    #[test]
+    fn decode_and_encode_every_variant()
+    {
+        for (opcode, instruction) in all_instructions()
+        {
+            assert_eq!(Instruction::decode(opcode), instruction, "decoding {:04X}", opcode);
+            assert_eq!(instruction.encode(), opcode, "encoding {:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn encode_is_the_inverse_of_decode_for_every_opcode()
+    {
+        for opcode in 0 ..= 0xFFFF
+        {
+            assert_eq!(Instruction::decode(opcode).encode(), opcode, "opcode {:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_kept()
+    {
+        for &opcode in &[0x5124, 0x8128, 0x9121, 0xE100, 0xF100, 0xF102, 0xF0FF]
+        {
+            assert_eq!(Instruction::decode(opcode), Instruction::Unknown(opcode));
+        }
+    }
+}
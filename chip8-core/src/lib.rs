@@ -0,0 +1,21 @@
+/*
+The interpreter and everything built around it that only needs the standard library.
+Frontends give it keys and frames and read back the screen, the sound state and the
+samples to play; the sound card output is behind the audio feature.
+*/
+
+pub mod chip8;
+pub mod quirks;
+pub mod error;
+pub mod instruction;
+pub mod disassembler;
+pub mod assembler;
+pub mod random;
+pub mod clock;
+pub mod audio;
+pub mod savestate;
+pub mod rewind;
+pub mod debugger;
+pub mod trace;
+pub mod screenshot;
+pub mod movie;
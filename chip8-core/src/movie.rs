@@ -0,0 +1,509 @@
+/*
+Input movies: every keypad change of a session with the frame it happened on. Input only
+reaches the interpreter between frames, so replaying a movie through Chip8 with the same
+rom, seed, quirks and instructions per frame reproduces the session exactly.
+
+Binary format, all numbers little endian:
+
+    magic       "CH8M"
+    version     u16
+    rom hash    u32     crc32 of the rom, as in save states
+    seed        u64     of RandomGenerator::seeded
+    quirks              as in save states
+    speed       u32     instructions per frame
+    length      u64     frames in the movie
+    count       u32     number of events
+    events              frame u64, key u8, pressed bool
+
+Text format, one statement per line and # for comments:
+
+    rom 1A2B3C4D
+    seed 42
+    quirks schip
+    speed 10
+    length 600
+    frame 120: press 5 for 4 frames
+    frame 300: press A
+    frame 310: release A
+
+Only the quirk presets have a name, movies recorded with other quirks need the binary format.
+*/
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use chip8::Chip8;
+use quirks::{self, Quirks};
+use savestate::{StateWriter, StateReader, SaveStateError};
+
+const MAGIC : &[u8; 4] = b"CH8M";
+const VERSION : u16 = 2;
+
+#[derive(Debug)]
+pub enum MovieError
+{
+    Io(io::Error),
+    NotAMovie,
+    UnsupportedVersion(u16),
+    RomMismatch { expected : u32, found : u32 },
+    QuirksMismatch { expected : Quirks, found : Quirks },
+    //Line numbers start at 1
+    Syntax { line : usize, message : String },
+    Corrupted(String),
+}
+
+impl fmt::Display for MovieError
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            MovieError::Io(ref error) => write!(f, "{}", error),
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(f, "movie version {} is not supported, expected {}", version, VERSION),
+            MovieError::RomMismatch { expected, found } => write!(f, "movie was recorded with rom {:08X} but rom {:08X} is loaded", found, expected),
+            MovieError::QuirksMismatch { expected, found } => write!(f, "movie was recorded with the {} quirks but the {} quirks are selected",
+                found.preset_name().unwrap_or("custom"), expected.preset_name().unwrap_or("custom")),
+            MovieError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+            MovieError::Corrupted(ref reason) => write!(f, "movie is corrupted: {}", reason),
+        }
+    }
+}
+
+impl error::Error for MovieError
+{
+}
+
+impl From<io::Error> for MovieError
+{
+    fn from(error : io::Error) -> MovieError
+    {
+        MovieError::Io(error)
+    }
+}
+
+impl From<SaveStateError> for MovieError
+{
+    fn from(error : SaveStateError) -> MovieError
+    {
+        match error
+        {
+            SaveStateError::Io(error) => MovieError::Io(error),
+            SaveStateError::Corrupted(reason) => MovieError::Corrupted(reason),
+            error => MovieError::Corrupted(error.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent
+{
+    //Applied before this frame runs
+    pub frame : u64,
+    pub key : u8,
+    pub pressed : bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie
+{
+    pub rom_hash : u32,
+    pub seed : u64,
+    pub quirks : Quirks,
+    pub instructions_per_frame : u32,
+    pub length : u64,
+    //Sorted by frame
+    pub events : Vec<InputEvent>,
+}
+
+impl Movie
+{
+    #[allow(dead_code)]
+    pub fn new(rom_hash : u32, seed : u64, quirks : Quirks, instructions_per_frame : u32) -> Movie
+    {
+        Movie
+        {
+            rom_hash,
+            seed,
+            quirks,
+            instructions_per_frame,
+            length : 0,
+            events : Vec::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8>
+    {
+        let mut writer = StateWriter::new();
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer.u32(self.rom_hash);
+        writer.u64(self.seed);
+        self.quirks.write_state(&mut writer);
+        writer.u32(self.instructions_per_frame);
+        writer.u64(self.length);
+        writer.u32(self.events.len() as u32);
+        for event in &self.events
+        {
+            writer.u64(event.frame);
+            writer.u8(event.key);
+            writer.bool(event.pressed);
+        }
+        writer.into_bytes()
+    }
+
+    #[allow(dead_code)]
+    pub fn decode(data : &[u8]) -> Result<Movie, MovieError>
+    {
+        if data.len() < MAGIC.len() || &data[0 .. 4] != MAGIC
+        {
+            return Err(MovieError::NotAMovie);
+        }
+        let mut reader = StateReader::new(&data[4 ..]);
+        let version = reader.u16()?;
+        if version != VERSION
+        {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let mut movie = Movie::new(reader.u32()?, reader.u64()?, Quirks::read_state(&mut reader)?, reader.u32()?);
+        movie.length = reader.u64()?;
+        let count = reader.u32()? as usize;
+        //Each event takes 10 bytes, a bad count should not allocate gigabytes
+        movie.events.reserve(count.min(reader.remaining() / 10));
+        for _ in 0 .. count
+        {
+            let event = InputEvent { frame : reader.u64()?, key : reader.u8()?, pressed : reader.bool()? };
+            if event.key > 0xF
+            {
+                return Err(MovieError::Corrupted(format!("key {} does not exist", event.key)));
+            }
+            if movie.events.last().is_some_and(|last| last.frame > event.frame)
+            {
+                return Err(MovieError::Corrupted(format!("event of frame {} is out of order", event.frame)));
+            }
+            movie.events.push(event);
+        }
+        if !reader.is_at_end()
+        {
+            return Err(MovieError::Corrupted("unexpected data after the events".to_string()));
+        }
+        Ok(movie)
+    }
+
+    //Presses followed by the release of the same key are written as "press K for N frames"
+    #[allow(dead_code)]
+    pub fn to_text(&self) -> String
+    {
+        let mut text = format!("rom {:08X}\nseed {}\nquirks {}\nspeed {}\nlength {}\n",
+            self.rom_hash, self.seed, self.quirks.preset_name().unwrap_or("custom"), self.instructions_per_frame, self.length);
+        let mut merged_releases = vec![false; self.events.len()];
+        for (i, event) in self.events.iter().enumerate()
+        {
+            if merged_releases[i]
+            {
+                continue;
+            }
+            if !event.pressed
+            {
+                text.push_str(&format!("frame {}: release {:X}\n", event.frame, event.key));
+                continue;
+            }
+            let release = self.events[i + 1 ..].iter().position(|next| next.key == event.key).map(|offset| i + 1 + offset)
+                .filter(|&j| !self.events[j].pressed && self.events[j].frame > event.frame);
+            match release
+            {
+                Some(j) =>
+                {
+                    merged_releases[j] = true;
+                    let frames = self.events[j].frame - event.frame;
+                    text.push_str(&format!("frame {}: press {:X} for {} frame{}\n", event.frame, event.key, frames, if frames == 1 { "" } else { "s" }));
+                }
+                None => text.push_str(&format!("frame {}: press {:X}\n", event.frame, event.key)),
+            }
+        }
+        text
+    }
+
+    //Without a length line the movie stops after the last event
+    #[allow(dead_code)]
+    pub fn parse_text(text : &str) -> Result<Movie, MovieError>
+    {
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut quirks = None;
+        let mut instructions_per_frame = None;
+        let mut length = None;
+        let mut events = Vec::new();
+
+        for (i, line) in text.lines().enumerate()
+        {
+            let syntax_error = |message : String| MovieError::Syntax { line : i + 1, message };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty()
+            {
+                continue;
+            }
+            let words : Vec<&str> = line.split_whitespace().collect();
+            let number = |word : Option<&&str>, what : &str| -> Result<u64, MovieError>
+            {
+                let word = word.ok_or_else(|| syntax_error(format!("expected {}", what)))?;
+                word.trim_end_matches(':').parse::<u64>().map_err(|_| syntax_error(format!("'{}' is not a valid {}", word, what)))
+            };
+            match words[0]
+            {
+                "rom" if words.len() == 2 => rom_hash = Some(u32::from_str_radix(words[1], 16).map_err(|_| syntax_error(format!("'{}' is not an hexadecimal rom hash", words[1])))?),
+                "seed" if words.len() == 2 => seed = Some(number(words.get(1), "seed")?),
+                "quirks" if words.len() == 2 => quirks = Some(Quirks::from_name(words[1])
+                    .ok_or_else(|| syntax_error(format!("'{}' is not a quirk preset, the presets are {}", words[1], quirks::PRESET_NAMES.join(", "))))?),
+                "speed" if words.len() == 2 => instructions_per_frame = Some(number(words.get(1), "number of instructions per frame")? as u32),
+                "length" if words.len() == 2 => length = Some(number(words.get(1), "number of frames")?),
+                "frame" =>
+                {
+                    if !words.get(1).is_some_and(|word| word.ends_with(':'))
+                    {
+                        return Err(syntax_error("expected frame <n>: press|release <key>".to_string()));
+                    }
+                    let frame = number(words.get(1), "frame number")?;
+                    let key = match words.get(3).and_then(|key| u8::from_str_radix(key, 16).ok())
+                    {
+                        Some(key) if key <= 0xF => key,
+                        _ => return Err(syntax_error("expected a key from 0 to F".to_string())),
+                    };
+                    match (words.get(2).cloned(), words.len())
+                    {
+                        (Some("press"), 4) => events.push(InputEvent { frame, key, pressed : true }),
+                        (Some("release"), 4) => events.push(InputEvent { frame, key, pressed : false }),
+                        (Some("press"), 7) if words[4] == "for" && (words[6] == "frames" || words[6] == "frame") =>
+                        {
+                            let duration = number(words.get(5), "number of frames")?;
+                            if duration == 0
+                            {
+                                return Err(syntax_error("a key is held for at least one frame".to_string()));
+                            }
+                            let release = frame.checked_add(duration).ok_or_else(|| syntax_error(format!("frame {} + {} is too far", frame, duration)))?;
+                            events.push(InputEvent { frame, key, pressed : true });
+                            events.push(InputEvent { frame : release, key, pressed : false });
+                        }
+                        _ => return Err(syntax_error("expected press <key> [for <n> frames] or release <key>".to_string())),
+                    }
+                }
+                word => return Err(syntax_error(format!("unknown statement '{}'", word))),
+            }
+        }
+
+        let missing = |what : &str| MovieError::Corrupted(format!("the movie has no {} line", what));
+        //Stable, events of the same frame keep the order of the file
+        events.sort_by_key(|event : &InputEvent| event.frame);
+        let mut movie = Movie::new(rom_hash.ok_or_else(|| missing("rom"))?, seed.ok_or_else(|| missing("seed"))?,
+            quirks.ok_or_else(|| missing("quirks"))?, instructions_per_frame.ok_or_else(|| missing("speed"))?);
+        movie.length = length.unwrap_or_else(|| events.last().map_or(0, |event| event.frame.saturating_add(1)));
+        movie.events = events;
+        Ok(movie)
+    }
+
+    //Files ending with .txt use the text format
+    #[allow(dead_code)]
+    pub fn save_to_file(&self, path : &Path) -> Result<(), MovieError>
+    {
+        let is_text = path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extension.eq_ignore_ascii_case("txt"));
+        let data = if is_text { self.to_text().into_bytes() } else { self.encode() };
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        Ok(())
+    }
+
+    //Either format, told apart by the magic
+    #[allow(dead_code)]
+    pub fn load_from_file(path : &Path) -> Result<Movie, MovieError>
+    {
+        let mut data = Vec::new();
+        let mut file = File::open(path)?;
+        file.read_to_end(&mut data)?;
+        if data.starts_with(MAGIC)
+        {
+            return Movie::decode(&data);
+        }
+        match String::from_utf8(data)
+        {
+            Ok(text) => Movie::parse_text(&text),
+            Err(_) => Err(MovieError::NotAMovie),
+        }
+    }
+}
+
+//Builds a movie from the keypad state of each frame
+pub struct Recorder
+{
+    movie : Movie,
+    keys : [bool; 16],
+}
+
+impl Recorder
+{
+    //The interpreter should have just been created with RandomGenerator::seeded(seed)
+    #[allow(dead_code)]
+    pub fn new(chip8 : &Chip8, seed : u64, instructions_per_frame : u32) -> Recorder
+    {
+        Recorder { movie : Movie::new(chip8.rom_hash(), seed, chip8.quirks(), instructions_per_frame), keys : [false; 16] }
+    }
+
+    //Notes the keys that changed since the previous frame, to call before each Chip8::run_frame
+    #[allow(dead_code)]
+    pub fn record(&mut self, chip8 : &Chip8)
+    {
+        for key in 0 .. 16u8
+        {
+            let pressed = chip8.is_key_pressed(key);
+            if pressed != self.keys[key as usize]
+            {
+                self.keys[key as usize] = pressed;
+                self.movie.events.push(InputEvent { frame : self.movie.length, key, pressed });
+            }
+        }
+        self.movie.length += 1;
+    }
+
+    #[allow(dead_code)]
+    pub fn movie(&self) -> &Movie
+    {
+        &self.movie
+    }
+
+    #[allow(dead_code)]
+    pub fn finish(self) -> Movie
+    {
+        self.movie
+    }
+}
+
+//Feeds the input of a movie to the interpreter
+pub struct Player
+{
+    movie : Movie,
+    next_event : usize,
+    frame : u64,
+}
+
+impl Player
+{
+    //The interpreter should have just been created with RandomGenerator::seeded(movie.seed)
+    #[allow(dead_code)]
+    pub fn new(movie : Movie, chip8 : &Chip8) -> Result<Player, MovieError>
+    {
+        if movie.rom_hash != chip8.rom_hash()
+        {
+            return Err(MovieError::RomMismatch { expected : chip8.rom_hash(), found : movie.rom_hash });
+        }
+        if movie.quirks != chip8.quirks()
+        {
+            return Err(MovieError::QuirksMismatch { expected : chip8.quirks(), found : movie.quirks });
+        }
+        Ok(Player { movie, next_event : 0, frame : 0 })
+    }
+
+    #[allow(dead_code)]
+    pub fn movie(&self) -> &Movie
+    {
+        &self.movie
+    }
+
+    #[allow(dead_code)]
+    pub fn frame(&self) -> u64
+    {
+        self.frame
+    }
+
+    #[allow(dead_code)]
+    pub fn is_finished(&self) -> bool
+    {
+        self.frame >= self.movie.length
+    }
+
+    //Applies the input of the coming frame, to call before each Chip8::run_frame
+    #[allow(dead_code)]
+    pub fn apply(&mut self, chip8 : &mut Chip8)
+    {
+        while let Some(event) = self.movie.events.get(self.next_event).cloned().filter(|event| event.frame <= self.frame)
+        {
+            if event.pressed
+            {
+                chip8.press_key(event.key);
+            }
+            else
+            {
+                chip8.release_key(event.key);
+            }
+            self.next_event += 1;
+        }
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use random::RandomGenerator;
+
+    fn movie() -> Movie
+    {
+        let mut movie = Movie::new(0x1A2B3C4D, 42, Quirks::super_chip(), 10);
+        movie.length = 600;
+        movie.events = vec![
+            InputEvent { frame : 120, key : 5, pressed : true },
+            InputEvent { frame : 124, key : 5, pressed : false },
+            InputEvent { frame : 300, key : 0xA, pressed : true },
+        ];
+        movie
+    }
+
+    #[test]
+    fn binary_round_trip()
+    {
+        assert_eq!(Movie::decode(&movie().encode()).unwrap(), movie());
+    }
+
+    #[test]
+    fn text_round_trip()
+    {
+        let text = movie().to_text();
+        assert!(text.contains("quirks schip\n"));
+        assert!(text.contains("frame 120: press 5 for 4 frames\n"));
+        assert_eq!(Movie::parse_text(&text).unwrap(), movie());
+    }
+
+    #[test]
+    fn movies_of_other_quirks_are_rejected()
+    {
+        let chip8 = Chip8::new(&[0x12, 0x00], Quirks::cosmac_vip(), RandomGenerator::seeded(42)).unwrap();
+        let mut movie = movie();
+        movie.rom_hash = chip8.rom_hash();
+        match Player::new(movie, &chip8)
+        {
+            Err(MovieError::QuirksMismatch { expected, found }) =>
+            {
+                assert_eq!(expected, Quirks::cosmac_vip());
+                assert_eq!(found, Quirks::super_chip());
+            }
+            _ => panic!("the quirks were not checked"),
+        }
+    }
+
+    #[test]
+    fn text_errors_report_their_line()
+    {
+        let header = "rom 1A2B3C4D\nseed 42\nquirks schip\nspeed 10\n";
+        let syntax_line = |text : &str| match Movie::parse_text(text)
+        {
+            Err(MovieError::Syntax { line, .. }) => line,
+            result => panic!("{:?} is not a syntax error", result),
+        };
+        assert_eq!(syntax_line(&format!("{}frame 18446744073709551615: press 1 for 2 frames\n", header)), 5);
+        assert_eq!(syntax_line("rom 1A2B3C4D\nquirks cosmac\n"), 2);
+        assert!(Movie::parse_text("rom 1A2B3C4D\nseed 42\nspeed 10\n").is_err());
+    }
+}
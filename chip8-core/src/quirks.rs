@@ -0,0 +1,216 @@
+/*
+Several CHIP-8 instructions behave differently depending on the interpreter a ROM
+was written for. Quirks selects one interpretation for each of them.
+See https://github.com/Timendus/chip8-test-suite#quirks-test for the details.
+*/
+
+use savestate::{StateWriter, StateReader, SaveStateError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadStoreQuirk
+{
+    //FX55/FX65 leave I untouched
+    Unchanged,
+    //FX55/FX65 add X to I
+    IncrementByX,
+    //FX55/FX65 add X + 1 to I
+    IncrementByXPlusOne,
+}
+
+pub const PRESET_NAMES : [&str; 4] = ["vip", "chip48", "schip", "xo-chip"];
+
+//0x200 bytes of interpreter area then at least one instruction
+pub const MIN_MEMORY_SIZE : usize = 0x202;
+//Everything a 16 bit address register can reach
+pub const MAX_MEMORY_SIZE : usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks
+{
+    //8XY6/8XYE shift VY and store the result in VX instead of shifting VX in place
+    pub shift_uses_vy : bool,
+    pub load_store : LoadStoreQuirk,
+    //BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx : bool,
+    //DXYN cuts sprites at the screen edges instead of wrapping them around
+    pub clip_sprites : bool,
+    //8XY1/8XY2/8XY3 set VF to 0
+    pub vf_reset : bool,
+    //DXYN waits for the next 60Hz tick before drawing
+    pub display_wait : bool,
+    //4096 bytes for most interpreters, 65536 for XO-CHIP
+    pub memory_size : usize,
+    //Nested 2NNN calls allowed before a stack overflow error, None for no limit
+    pub stack_depth : Option<usize>,
+    //2NNN also writes the return addresses below 0xED0 like the VIP, for ROMs that inspect them
+    pub stack_in_memory : bool,
+}
+
+impl Quirks
+{
+    //One of PRESET_NAMES, case insensitive
+    #[allow(dead_code)]
+    pub fn from_name(name : &str) -> Option<Quirks>
+    {
+        match &*name.to_lowercase()
+        {
+            "vip" | "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "super-chip" => Some(Quirks::super_chip()),
+            "xo-chip" | "xochip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+
+    //The name of the preset these quirks are, None for custom quirks
+    #[allow(dead_code)]
+    pub fn preset_name(&self) -> Option<&'static str>
+    {
+        PRESET_NAMES.iter().cloned().find(|name| Quirks::from_name(name) == Some(*self))
+    }
+
+    //XO-CHIP mode is the 64K address space, the other interpreters have no F000 NNNN to skip over
+    #[allow(dead_code)]
+    pub fn is_xo_chip(&self) -> bool
+    {
+        self.memory_size == MAX_MEMORY_SIZE
+    }
+
+    #[allow(dead_code)]
+    pub fn cosmac_vip() -> Quirks
+    {
+        Quirks
+        {
+            shift_uses_vy : true,
+            load_store : LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx : false,
+            clip_sprites : true,
+            vf_reset : true,
+            display_wait : true,
+            memory_size : 4096,
+            stack_depth : Some(12),
+            stack_in_memory : false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn chip48() -> Quirks
+    {
+        Quirks
+        {
+            shift_uses_vy : false,
+            load_store : LoadStoreQuirk::IncrementByX,
+            jump_uses_vx : true,
+            clip_sprites : true,
+            vf_reset : false,
+            display_wait : false,
+            memory_size : 4096,
+            stack_depth : Some(16),
+            stack_in_memory : false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn super_chip() -> Quirks
+    {
+        Quirks
+        {
+            shift_uses_vy : false,
+            load_store : LoadStoreQuirk::Unchanged,
+            jump_uses_vx : true,
+            clip_sprites : true,
+            vf_reset : false,
+            display_wait : false,
+            memory_size : 4096,
+            stack_depth : Some(16),
+            stack_in_memory : false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn xo_chip() -> Quirks
+    {
+        Quirks
+        {
+            shift_uses_vy : true,
+            load_store : LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx : false,
+            clip_sprites : false,
+            vf_reset : false,
+            display_wait : false,
+            memory_size : 65536,
+            stack_depth : Some(16),
+            stack_in_memory : false,
+        }
+    }
+}
+
+impl Quirks
+{
+    pub fn write_state(&self, writer : &mut StateWriter)
+    {
+        writer.bool(self.shift_uses_vy);
+        writer.u8(match self.load_store
+        {
+            LoadStoreQuirk::Unchanged => 0,
+            LoadStoreQuirk::IncrementByX => 1,
+            LoadStoreQuirk::IncrementByXPlusOne => 2,
+        });
+        writer.bool(self.jump_uses_vx);
+        writer.bool(self.clip_sprites);
+        writer.bool(self.vf_reset);
+        writer.bool(self.display_wait);
+        writer.u32(self.memory_size as u32);
+        //0 for no limit
+        writer.u32(self.stack_depth.map_or(0, |depth| depth as u32));
+        writer.bool(self.stack_in_memory);
+    }
+
+    pub fn read_state(reader : &mut StateReader) -> Result<Quirks, SaveStateError>
+    {
+        let shift_uses_vy = reader.bool()?;
+        let load_store = match reader.u8()?
+        {
+            0 => LoadStoreQuirk::Unchanged,
+            1 => LoadStoreQuirk::IncrementByX,
+            2 => LoadStoreQuirk::IncrementByXPlusOne,
+            value => return Err(SaveStateError::Corrupted(format!("unknown load/store quirk {}", value))),
+        };
+        let jump_uses_vx = reader.bool()?;
+        let clip_sprites = reader.bool()?;
+        let vf_reset = reader.bool()?;
+        let display_wait = reader.bool()?;
+        let memory_size = reader.u32()? as usize;
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&memory_size)
+        {
+            return Err(SaveStateError::Corrupted(format!("memory size {} is not supported", memory_size)));
+        }
+        let stack_depth = match reader.u32()?
+        {
+            0 => None,
+            depth => Some(depth as usize),
+        };
+        let stack_in_memory = reader.bool()?;
+
+        Ok(Quirks
+        {
+            shift_uses_vy,
+            load_store,
+            jump_uses_vx,
+            clip_sprites,
+            vf_reset,
+            display_wait,
+            memory_size,
+            stack_depth,
+            stack_in_memory,
+        })
+    }
+}
+
+impl Default for Quirks
+{
+    fn default() -> Quirks
+    {
+        Quirks::cosmac_vip()
+    }
+}
@@ -0,0 +1,211 @@
+/*
+Random number sources for CXNN. They are all deterministic so that a run can be replayed
+exactly, the frontend picks a seed when it wants a different game every time.
+*/
+
+use savestate::{StateWriter, StateReader, SaveStateError};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RandomGenerator
+{
+    //xorshift64*, state is never 0
+    Seeded { state : u64 },
+    /*
+    Same algorithm as the COSMAC VIP interpreter: an index register is incremented before
+    every instruction, CXNN adds the byte of the interpreter page 0x0100-0x01FF at that
+    index to the previous result. The page is not bundled, it comes from a dump of the VIP
+    interpreter.
+    */
+    CosmacVip { page : Vec<u8>, index : u8, value : u8 },
+    //Returns the given bytes in order and starts over at the end, for tests
+    Scripted { sequence : Vec<u8>, position : usize },
+}
+
+impl RandomGenerator
+{
+    #[allow(dead_code)]
+    pub fn seeded(seed : u64) -> RandomGenerator
+    {
+        //0 is the only fixed point of xorshift
+        let state = if seed == 0 { 0x9E3779B97F4A7C15 } else { seed };
+        RandomGenerator::Seeded { state }
+    }
+
+    #[allow(dead_code)]
+    pub fn cosmac_vip(interpreter_page : [u8; 256]) -> RandomGenerator
+    {
+        RandomGenerator::CosmacVip { page : interpreter_page.to_vec(), index : 0, value : 0 }
+    }
+
+    #[allow(dead_code)]
+    pub fn scripted(sequence : Vec<u8>) -> RandomGenerator
+    {
+        RandomGenerator::Scripted { sequence, position : 0 }
+    }
+
+    //Called by the interpreter before each instruction
+    pub fn step(&mut self)
+    {
+        if let RandomGenerator::CosmacVip { ref mut index, .. } = *self
+        {
+            *index = index.wrapping_add(1);
+        }
+    }
+
+    pub fn next_byte(&mut self) -> u8
+    {
+        match *self
+        {
+            RandomGenerator::Seeded { ref mut state } =>
+            {
+                *state ^= *state >> 12;
+                *state ^= *state << 25;
+                *state ^= *state >> 27;
+                (state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+            }
+            RandomGenerator::CosmacVip { ref page, index, ref mut value } =>
+            {
+                *value = value.wrapping_add(page[index as usize]);
+                *value
+            }
+            RandomGenerator::Scripted { ref sequence, ref mut position } =>
+            {
+                if sequence.is_empty()
+                {
+                    return 0;
+                }
+                let byte = sequence[*position % sequence.len()];
+                *position = (*position + 1) % sequence.len();
+                byte
+            }
+        }
+    }
+
+    pub fn write_state(&self, writer : &mut StateWriter)
+    {
+        match *self
+        {
+            RandomGenerator::Seeded { state } =>
+            {
+                writer.u8(0);
+                writer.u64(state);
+            }
+            RandomGenerator::CosmacVip { ref page, index, value } =>
+            {
+                writer.u8(1);
+                writer.bytes(page);
+                writer.u8(index);
+                writer.u8(value);
+            }
+            RandomGenerator::Scripted { ref sequence, position } =>
+            {
+                writer.u8(2);
+                writer.byte_vec(sequence);
+                writer.u32(position as u32);
+            }
+        }
+    }
+
+    pub fn read_state(reader : &mut StateReader) -> Result<RandomGenerator, SaveStateError>
+    {
+        match reader.u8()?
+        {
+            0 =>
+            {
+                let state = reader.u64()?;
+                if state == 0
+                {
+                    return Err(SaveStateError::Corrupted("random state is 0".to_string()));
+                }
+                Ok(RandomGenerator::Seeded { state })
+            }
+            1 =>
+            {
+                let page = reader.bytes(256)?.to_vec();
+                let index = reader.u8()?;
+                let value = reader.u8()?;
+                Ok(RandomGenerator::CosmacVip { page, index, value })
+            }
+            2 =>
+            {
+                let sequence = reader.byte_vec()?;
+                let position = reader.u32()? as usize;
+                if position != 0 && position >= sequence.len()
+                {
+                    return Err(SaveStateError::Corrupted(format!("random position {} is past the end", position)));
+                }
+                Ok(RandomGenerator::Scripted { sequence, position })
+            }
+            value => Err(SaveStateError::Corrupted(format!("unknown random generator {}", value))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use chip8::Chip8;
+    use quirks::Quirks;
+
+    //Page of a made up interpreter whose byte at 0x01NN is NN
+    fn counting_page() -> [u8; 256]
+    {
+        let mut page = [0; 256];
+        for (i, byte) in page.iter_mut().enumerate()
+        {
+            *byte = i as u8;
+        }
+        page
+    }
+
+    #[test]
+    fn cosmac_vip_adds_the_page_byte_of_the_instruction_count()
+    {
+        //C0FF, 00E0, C1FF, C20F: the index is 1, 2, 3 and 4 when they run
+        let rom = [0xC0, 0xFF, 0x00, 0xE0, 0xC1, 0xFF, 0xC2, 0x0F];
+        let mut chip8 = Chip8::new(&rom, Quirks::cosmac_vip(), RandomGenerator::cosmac_vip(counting_page())).unwrap();
+        for _ in 0 .. 4
+        {
+            chip8.run_one_cycle().unwrap();
+        }
+        //0 + 1, then 1 + 3, then (4 + 4) & 0x0F
+        assert_eq!(&chip8.registers()[0 .. 3], &[1, 4, 8]);
+    }
+
+    #[test]
+    fn cosmac_vip_sequence()
+    {
+        let mut page = [0; 256];
+        page[1] = 0x9C;
+        page[2] = 0x7A;
+        page[3] = 0xF1;
+        let mut random = RandomGenerator::cosmac_vip(page);
+        let mut sequence = Vec::new();
+        for _ in 0 .. 4
+        {
+            random.step();
+            sequence.push(random.next_byte());
+        }
+        assert_eq!(sequence, [0x9C, 0x16, 0x07, 0x07]);
+        //Several CXNN in a row without an instruction in between keep adding the same byte
+        assert_eq!(random.next_byte(), 0x07);
+    }
+
+    #[test]
+    fn save_state_round_trip()
+    {
+        let mut generators = vec![RandomGenerator::seeded(42), RandomGenerator::cosmac_vip(counting_page()), RandomGenerator::scripted(vec![1, 2, 3])];
+        for random in &mut generators
+        {
+            random.step();
+            random.next_byte();
+            let mut writer = StateWriter::new();
+            random.write_state(&mut writer);
+            let data = writer.into_bytes();
+            let mut reader = StateReader::new(&data);
+            assert_eq!(RandomGenerator::read_state(&mut reader).unwrap(), *random);
+            assert!(reader.is_at_end());
+        }
+    }
+}
@@ -0,0 +1,521 @@
+/*
+Rewind history. A snapshot of the interpreter is recorded before every frame. Every
+KEYFRAME_INTERVAL frames the snapshot is stored whole, the ones in between only store
+the bytes that differ from the last keyframe. Both are run length encoded so that a
+frame where nothing but the registers changed takes a few dozen bytes.
+The oldest snapshots are dropped when the history goes over its memory budget.
+*/
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use chip8::Chip8;
+use error::Chip8Error;
+use savestate::{StateWriter, StateReader, SaveStateError};
+
+pub const DEFAULT_MEMORY_BUDGET : usize = 32 * 1024 * 1024;
+const KEYFRAME_INTERVAL : u32 = 60;
+
+#[derive(Debug)]
+pub enum RewindError
+{
+    //A snapshot does not decode to a valid state, the history is cleared
+    Corrupted(SaveStateError),
+    //Running the instructions up to the target failed
+    Emulation(Chip8Error),
+}
+
+impl fmt::Display for RewindError
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            RewindError::Corrupted(ref error) => write!(f, "rewind history is damaged: {}", error),
+            RewindError::Emulation(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for RewindError
+{
+}
+
+impl From<SaveStateError> for RewindError
+{
+    fn from(error : SaveStateError) -> RewindError
+    {
+        RewindError::Corrupted(error)
+    }
+}
+
+impl From<Chip8Error> for RewindError
+{
+    fn from(error : Chip8Error) -> RewindError
+    {
+        RewindError::Emulation(error)
+    }
+}
+
+struct Snapshot
+{
+    data : Vec<u8>,
+    size : usize,
+    keyframe : bool,
+    instruction_count : u64,
+}
+
+pub struct Rewind
+{
+    snapshots : VecDeque<Snapshot>,
+    memory_budget : usize,
+    memory_used : usize,
+    //uncompressed copy of the newest keyframe, the reference of the next deltas
+    last_keyframe : Option<Vec<u8>>,
+    frames_since_keyframe : u32,
+}
+
+impl Rewind
+{
+    #[allow(dead_code)]
+    pub fn new(memory_budget : usize) -> Rewind
+    {
+        Rewind
+        {
+            snapshots : VecDeque::new(),
+            memory_budget,
+            memory_used : 0,
+            last_keyframe : None,
+            frames_since_keyframe : 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize
+    {
+        self.snapshots.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool
+    {
+        self.snapshots.is_empty()
+    }
+
+    #[allow(dead_code)]
+    pub fn memory_used(&self) -> usize
+    {
+        self.memory_used
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self)
+    {
+        self.snapshots.clear();
+        self.memory_used = 0;
+        self.last_keyframe = None;
+    }
+
+    //Has to be called before each frame is run
+    #[allow(dead_code)]
+    pub fn record(&mut self, chip8 : &Chip8)
+    {
+        let mut writer = StateWriter::new();
+        chip8.write_state(&mut writer);
+        let state = writer.into_bytes();
+
+        let keyframe = self.last_keyframe.is_none() || self.frames_since_keyframe >= KEYFRAME_INTERVAL;
+        let snapshot = if keyframe
+        {
+            let data = compress(&state, &[]);
+            self.frames_since_keyframe = 0;
+            Snapshot { data, size : state.len(), keyframe : true, instruction_count : chip8.instruction_count() }
+        }
+        else
+        {
+            let data = compress(&state, self.last_keyframe.as_ref().unwrap());
+            self.frames_since_keyframe += 1;
+            Snapshot { data, size : state.len(), keyframe : false, instruction_count : chip8.instruction_count() }
+        };
+        if keyframe
+        {
+            self.last_keyframe = Some(state);
+        }
+
+        self.memory_used += snapshot.data.len();
+        self.snapshots.push_back(snapshot);
+
+        while self.memory_used > self.memory_budget && self.snapshots.len() > 1
+        {
+            self.drop_oldest_keyframe_group();
+        }
+    }
+
+    //Goes back to the start of the last recorded frame and forgets it, false once the history is empty
+    #[allow(dead_code)]
+    pub fn step_back(&mut self, chip8 : &mut Chip8) -> Result<bool, RewindError>
+    {
+        if self.snapshots.is_empty()
+        {
+            return Ok(false);
+        }
+        let index = self.snapshots.len() - 1;
+        self.restore(index, chip8)?;
+        self.truncate(index);
+        Ok(true)
+    }
+
+    /*
+    Goes back count instructions: restores the last frame that started before the target
+    and runs the instructions between the start of that frame and the target again.
+    Returns false without changing anything if the history does not go back that far.
+    */
+    #[allow(dead_code)]
+    pub fn rewind_instructions(&mut self, chip8 : &mut Chip8, count : u64) -> Result<bool, RewindError>
+    {
+        if count > chip8.instruction_count()
+        {
+            return Ok(false);
+        }
+        let target = chip8.instruction_count() - count;
+        let index = match self.snapshots.iter().rposition(|snapshot| snapshot.instruction_count <= target)
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+
+        self.restore(index, chip8)?;
+        //The frame is run again from its start, so its snapshot stays valid
+        self.truncate(index + 1);
+        while chip8.instruction_count() < target && !chip8.is_halted() && !chip8.is_waiting_for_display_refresh()
+        {
+            chip8.run_one_cycle()?;
+        }
+        Ok(true)
+    }
+
+    //The interpreter is left untouched if the snapshot is damaged, the history is cleared then
+    fn restore(&mut self, index : usize, chip8 : &mut Chip8) -> Result<(), SaveStateError>
+    {
+        let result = self.decode(index).and_then(|state| chip8.read_state(&mut StateReader::new(&state)));
+        if result.is_err()
+        {
+            self.clear();
+        }
+        result
+    }
+
+    fn decode(&self, index : usize) -> Result<Vec<u8>, SaveStateError>
+    {
+        //Snapshots always start with a keyframe, deltas cannot outlive theirs
+        let keyframe_index = (0 .. index + 1).rev().find(|i| self.snapshots[*i].keyframe).ok_or_else(|| SaveStateError::Corrupted("delta without a keyframe".to_string()))?;
+        let keyframe = &self.snapshots[keyframe_index];
+        let mut state = decompress(&keyframe.data, &[], keyframe.size)?;
+        if keyframe_index != index
+        {
+            let snapshot = &self.snapshots[index];
+            state = decompress(&snapshot.data, &state, snapshot.size)?;
+        }
+        Ok(state)
+    }
+
+    //Keeps the snapshots before index
+    fn truncate(&mut self, index : usize)
+    {
+        while self.snapshots.len() > index
+        {
+            let snapshot = self.snapshots.pop_back().unwrap();
+            self.memory_used -= snapshot.data.len();
+            if snapshot.keyframe
+            {
+                self.last_keyframe = None;
+            }
+        }
+        if self.last_keyframe.is_some()
+        {
+            self.frames_since_keyframe = self.snapshots.iter().rev().take_while(|snapshot| !snapshot.keyframe).count() as u32;
+        }
+    }
+
+    //Deltas cannot outlive their keyframe so they go together
+    fn drop_oldest_keyframe_group(&mut self)
+    {
+        if let Some(snapshot) = self.snapshots.pop_front()
+        {
+            self.memory_used -= snapshot.data.len();
+        }
+        while self.snapshots.front().is_some_and(|snapshot| !snapshot.keyframe)
+        {
+            let snapshot = self.snapshots.pop_front().unwrap();
+            self.memory_used -= snapshot.data.len();
+        }
+        if self.snapshots.is_empty()
+        {
+            self.last_keyframe = None;
+        }
+    }
+}
+
+impl Default for Rewind
+{
+    fn default() -> Rewind
+    {
+        Rewind::new(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+//Shorter runs of identical bytes are cheaper to keep in the literal
+const MIN_SAME_RUN : usize = 4;
+
+fn reference_byte(reference : &[u8], index : usize) -> u8
+{
+    match reference.get(index)
+    {
+        Some(byte) => *byte,
+        None => 0,
+    }
+}
+
+/*
+The data is xored with the reference and stored as a sequence of
+[bytes identical to the reference][literal length][literal bytes],
+the lengths being LEB128 varints.
+*/
+fn compress(data : &[u8], reference : &[u8]) -> Vec<u8>
+{
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len()
+    {
+        let same_start = i;
+        while i < data.len() && data[i] == reference_byte(reference, i)
+        {
+            i += 1;
+        }
+        let same = i - same_start;
+
+        let literal_start = i;
+        let mut same_run = 0;
+        while i < data.len() && same_run < MIN_SAME_RUN
+        {
+            if data[i] == reference_byte(reference, i)
+            {
+                same_run += 1;
+            }
+            else
+            {
+                same_run = 0;
+            }
+            i += 1;
+        }
+        //The trailing identical bytes start the next token
+        if same_run == MIN_SAME_RUN
+        {
+            i -= same_run;
+        }
+
+        write_varint(&mut output, same);
+        write_varint(&mut output, i - literal_start);
+        for (j, &byte) in data[literal_start .. i].iter().enumerate()
+        {
+            output.push(byte ^ reference_byte(reference, literal_start + j));
+        }
+    }
+    output
+}
+
+fn decompress(data : &[u8], reference : &[u8], size : usize) -> Result<Vec<u8>, SaveStateError>
+{
+    let corrupted = || SaveStateError::Corrupted("rewind snapshot does not decompress".to_string());
+    let mut output = Vec::with_capacity(size);
+    let mut position = 0;
+    while position < data.len()
+    {
+        let same = read_varint(data, &mut position).ok_or_else(corrupted)?;
+        let literal = read_varint(data, &mut position).ok_or_else(corrupted)?;
+        //Never more than the size of the state, so a damaged length cannot allocate without bounds
+        if same > size - output.len() || literal > size - output.len() - same
+        {
+            return Err(corrupted());
+        }
+        for _ in 0 .. same
+        {
+            let byte = reference_byte(reference, output.len());
+            output.push(byte);
+        }
+        let literal_bytes = data.get(position .. position + literal).ok_or_else(corrupted)?;
+        for &byte in literal_bytes
+        {
+            let byte = byte ^ reference_byte(reference, output.len());
+            output.push(byte);
+        }
+        position += literal;
+    }
+    if output.len() != size
+    {
+        return Err(corrupted());
+    }
+    Ok(output)
+}
+
+fn write_varint(output : &mut Vec<u8>, value : usize)
+{
+    let mut value = value;
+    while value >= 0x80
+    {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+//None if the data ends early or the value does not fit in a usize
+fn read_varint(data : &[u8], position : &mut usize) -> Option<usize>
+{
+    let mut value : usize = 0;
+    let mut shift = 0;
+    loop
+    {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        if shift >= usize::BITS
+        {
+            return None;
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0
+        {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use quirks::Quirks;
+    use random::RandomGenerator;
+
+    //Counts in V0 and draws a random sprite every loop, so that the memory and screen change
+    const ROM : [u8; 10] = [0x70, 0x01, 0xC1, 0xFF, 0xA3, 0x00, 0xD1, 0x15, 0x12, 0x00];
+
+    fn new_chip8() -> Chip8
+    {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.display_wait = false;
+        Chip8::new(&ROM, quirks, RandomGenerator::seeded(1)).unwrap()
+    }
+
+    fn state_of(chip8 : &Chip8) -> Vec<u8>
+    {
+        let mut writer = StateWriter::new();
+        chip8.write_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn compress_round_trip()
+    {
+        let reference : Vec<u8> = (0 .. 1000).map(|i| (i * 7) as u8).collect();
+        let mut data = reference.clone();
+        data[3] ^= 0xFF;
+        data[500 .. 700].iter_mut().for_each(|byte| *byte = 0x55);
+        data.extend_from_slice(&[1, 2, 3]);
+
+        for &(data, reference) in &[(&data, &reference), (&data, &Vec::new()), (&reference, &reference), (&Vec::new(), &reference)]
+        {
+            let compressed = compress(data, reference);
+            assert_eq!(decompress(&compressed, reference, data.len()).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn unchanged_bytes_are_cheap()
+    {
+        let reference = vec![0x42; 4096];
+        let mut data = reference.clone();
+        data[2000] = 0;
+        let compressed = compress(&data, &reference);
+        assert!(compressed.len() < 16, "{} bytes", compressed.len());
+        assert_eq!(decompress(&compressed, &reference, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn damaged_data_does_not_decompress()
+    {
+        let reference = vec![0; 64];
+        let data : Vec<u8> = (0 .. 64).collect();
+        let compressed = compress(&data, &reference);
+        assert!(decompress(&compressed[.. compressed.len() - 1], &reference, data.len()).is_err());
+        assert!(decompress(&compressed, &reference, data.len() - 1).is_err());
+        assert!(decompress(&[0xFF; 12], &reference, data.len()).is_err());
+        assert!(decompress(&[0x80], &reference, data.len()).is_err());
+    }
+
+    #[test]
+    fn step_back_restores_every_frame()
+    {
+        let mut chip8 = new_chip8();
+        let mut rewind = Rewind::default();
+        let mut states = Vec::new();
+        for _ in 0 .. KEYFRAME_INTERVAL * 2 + 10
+        {
+            rewind.record(&chip8);
+            states.push(state_of(&chip8));
+            chip8.run_frame(7).unwrap();
+        }
+        while let Some(state) = states.pop()
+        {
+            assert!(rewind.step_back(&mut chip8).unwrap());
+            assert_eq!(state_of(&chip8), state);
+        }
+        assert!(!rewind.step_back(&mut chip8).unwrap());
+    }
+
+    #[test]
+    fn rewind_instructions_goes_back_exactly()
+    {
+        let mut chip8 = new_chip8();
+        let mut rewind = Rewind::default();
+        let mut states = vec![state_of(&chip8)];
+        for _ in 0 .. 20
+        {
+            rewind.record(&chip8);
+            for _ in 0 .. 7
+            {
+                chip8.run_one_cycle().unwrap();
+                states.push(state_of(&chip8));
+            }
+            chip8.tick_timers();
+        }
+
+        for &count in &[1, 5, 7, 30]
+        {
+            let target = chip8.instruction_count() - count;
+            assert!(rewind.rewind_instructions(&mut chip8, count).unwrap());
+            assert_eq!(chip8.instruction_count(), target);
+            let mut expected = Chip8::new(&ROM, Quirks::cosmac_vip(), RandomGenerator::seeded(1)).unwrap();
+            expected.read_state(&mut StateReader::new(&states[target as usize])).unwrap();
+            assert_eq!(chip8.state(), expected.state());
+            assert_eq!(chip8.memory(), expected.memory());
+        }
+        let too_far = chip8.instruction_count() + 1;
+        assert!(!rewind.rewind_instructions(&mut chip8, too_far).unwrap());
+    }
+
+    #[test]
+    fn damaged_snapshots_are_errors()
+    {
+        let mut chip8 = new_chip8();
+        let mut rewind = Rewind::default();
+        rewind.record(&chip8);
+        chip8.run_frame(7).unwrap();
+        let before = state_of(&chip8);
+        rewind.snapshots[0].data.truncate(3);
+        assert!(matches!(rewind.step_back(&mut chip8), Err(RewindError::Corrupted(_))));
+        assert_eq!(state_of(&chip8), before);
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,434 @@
+/*
+On-disk save state format, all numbers little endian:
+
+    magic       "CH8S"
+    version     u16
+    reserved    u16
+    rom hash    u32     crc32 of the rom the state was taken with
+    length      u32     size of the payload
+    payload             written by Chip8::write_state
+    checksum    u32     crc32 of the payload
+
+States from another version or another rom are rejected instead of being half loaded.
+*/
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use chip8::Chip8;
+
+const MAGIC : &[u8; 4] = b"CH8S";
+pub const VERSION : u16 = 3;
+const HEADER_SIZE : usize = 16;
+pub const SLOTS : u32 = 10;
+
+#[derive(Debug)]
+pub enum SaveStateError
+{
+    Io(io::Error),
+    NotASaveState,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    RomMismatch { expected : u32, found : u32 },
+    //The payload ends early or holds values that cannot be restored
+    Corrupted(String),
+}
+
+impl fmt::Display for SaveStateError
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            SaveStateError::Io(ref error) => write!(f, "{}", error),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported, expected {}", version, VERSION),
+            SaveStateError::ChecksumMismatch => write!(f, "save state checksum does not match, the file is damaged"),
+            SaveStateError::RomMismatch { expected, found } => write!(f, "save state was made with rom {:08X} but rom {:08X} is loaded", found, expected),
+            SaveStateError::Corrupted(ref reason) => write!(f, "save state is corrupted: {}", reason),
+        }
+    }
+}
+
+impl error::Error for SaveStateError
+{
+}
+
+impl From<io::Error> for SaveStateError
+{
+    fn from(error : io::Error) -> SaveStateError
+    {
+        SaveStateError::Io(error)
+    }
+}
+
+#[allow(dead_code)]
+pub fn save(chip8 : &Chip8) -> Vec<u8>
+{
+    let mut payload = StateWriter::new();
+    chip8.write_state(&mut payload);
+    let payload = payload.into_bytes();
+
+    let mut writer = StateWriter::new();
+    writer.bytes(MAGIC);
+    writer.u16(VERSION);
+    writer.u16(0);
+    writer.u32(chip8.rom_hash());
+    writer.u32(payload.len() as u32);
+    writer.bytes(&payload);
+    writer.u32(crc32(&payload));
+    writer.into_bytes()
+}
+
+//The state is only applied if it is entirely valid
+#[allow(dead_code)]
+pub fn load(chip8 : &mut Chip8, data : &[u8]) -> Result<(), SaveStateError>
+{
+    if data.len() < HEADER_SIZE || &data[0 .. 4] != MAGIC
+    {
+        return Err(SaveStateError::NotASaveState);
+    }
+
+    let mut header = StateReader::new(&data[4 .. HEADER_SIZE]);
+    let version = header.u16()?;
+    let _reserved = header.u16()?;
+    let rom_hash = header.u32()?;
+    let length = header.u32()? as usize;
+
+    if version != VERSION
+    {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    if data.len() != HEADER_SIZE + length + 4
+    {
+        return Err(SaveStateError::Corrupted(format!("expected {} bytes but got {}", HEADER_SIZE + length + 4, data.len())));
+    }
+
+    let payload = &data[HEADER_SIZE .. HEADER_SIZE + length];
+    let checksum = StateReader::new(&data[HEADER_SIZE + length ..]).u32()?;
+    if crc32(payload) != checksum
+    {
+        return Err(SaveStateError::ChecksumMismatch);
+    }
+    if rom_hash != chip8.rom_hash()
+    {
+        return Err(SaveStateError::RomMismatch { expected : chip8.rom_hash(), found : rom_hash });
+    }
+
+    chip8.read_state(&mut StateReader::new(payload))
+}
+
+#[allow(dead_code)]
+pub fn save_to_file(chip8 : &Chip8, path : &Path) -> Result<(), SaveStateError>
+{
+    let mut file = File::create(path)?;
+    file.write_all(&save(chip8))?;
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn load_from_file(chip8 : &mut Chip8, path : &Path) -> Result<(), SaveStateError>
+{
+    let mut data = Vec::new();
+    let mut file = File::open(path)?;
+    file.read_to_end(&mut data)?;
+    load(chip8, &data)
+}
+
+//Slot n of game.ch8 is game.ch8.state<n>
+#[allow(dead_code)]
+pub fn slot_path(rom_path : &Path, slot : u32) -> PathBuf
+{
+    let mut path = rom_path.as_os_str().to_owned();
+    path.push(format!(".state{}", slot));
+    PathBuf::from(path)
+}
+
+#[derive(Default)]
+pub struct StateWriter
+{
+    data : Vec<u8>,
+}
+
+impl StateWriter
+{
+    pub fn new() -> StateWriter
+    {
+        StateWriter { data : Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8>
+    {
+        self.data
+    }
+
+    pub fn bytes(&mut self, bytes : &[u8])
+    {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value : u8)
+    {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value : bool)
+    {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value : u16)
+    {
+        self.data.push(value as u8);
+        self.data.push((value >> 8) as u8);
+    }
+
+    pub fn u32(&mut self, value : u32)
+    {
+        self.u16(value as u16);
+        self.u16((value >> 16) as u16);
+    }
+
+    pub fn u64(&mut self, value : u64)
+    {
+        self.u32(value as u32);
+        self.u32((value >> 32) as u32);
+    }
+
+    //Length prefixed
+    pub fn byte_vec(&mut self, bytes : &[u8])
+    {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+}
+
+pub struct StateReader<'a>
+{
+    data : &'a [u8],
+    position : usize,
+}
+
+impl<'a> StateReader<'a>
+{
+    pub fn new(data : &'a [u8]) -> StateReader<'a>
+    {
+        StateReader { data, position : 0 }
+    }
+
+    pub fn is_at_end(&self) -> bool
+    {
+        self.position == self.data.len()
+    }
+
+    pub fn remaining(&self) -> usize
+    {
+        self.data.len() - self.position
+    }
+
+    pub fn bytes(&mut self, count : usize) -> Result<&'a [u8], SaveStateError>
+    {
+        if self.data.len() - self.position < count
+        {
+            return Err(SaveStateError::Corrupted("unexpected end of data".to_string()));
+        }
+        let bytes = &self.data[self.position .. self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError>
+    {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError>
+    {
+        match self.u8()?
+        {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(SaveStateError::Corrupted(format!("{} is not a boolean", value))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError>
+    {
+        let bytes = self.bytes(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError>
+    {
+        let low = self.u16()? as u32;
+        let high = self.u16()? as u32;
+        Ok(low | high << 16)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError>
+    {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(low | high << 32)
+    }
+
+    pub fn byte_vec(&mut self) -> Result<Vec<u8>, SaveStateError>
+    {
+        let length = self.u32()? as usize;
+        Ok(self.bytes(length)?.to_vec())
+    }
+}
+
+//CRC-32 as used by zip and png
+pub fn crc32(data : &[u8]) -> u32
+{
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data
+    {
+        crc ^= *byte as u32;
+        for _ in 0 .. 8
+        {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use quirks::Quirks;
+    use random::RandomGenerator;
+
+    //Draws, calls a subroutine and waits there with keys and timers set
+    const ROM : [u8; 12] = [0x60, 0x05, 0xA2, 0x0A, 0xD0, 0x05, 0x22, 0x08, 0x12, 0x08, 0xF0, 0x90];
+
+    fn new_chip8(quirks : Quirks) -> Chip8
+    {
+        Chip8::new(&ROM, quirks, RandomGenerator::seeded(7)).unwrap()
+    }
+
+    fn running_chip8() -> Chip8
+    {
+        let mut chip8 = new_chip8(Quirks::cosmac_vip());
+        chip8.press_key(3);
+        chip8.set_delay_timer(30);
+        for _ in 0 .. 3
+        {
+            chip8.run_frame(10).unwrap();
+        }
+        chip8
+    }
+
+    //Frames a modified payload again with a valid header and checksum
+    fn reframe(state : &[u8], edit : &dyn Fn(&mut Vec<u8>)) -> Vec<u8>
+    {
+        let mut payload = state[HEADER_SIZE .. state.len() - 4].to_vec();
+        edit(&mut payload);
+        let mut data = state[.. HEADER_SIZE].to_vec();
+        data[12 .. 16].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(&payload);
+        data.extend_from_slice(&crc32(&payload).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn save_and_load_round_trip()
+    {
+        let chip8 = running_chip8();
+        let state = save(&chip8);
+
+        let mut loaded = new_chip8(Quirks::xo_chip());
+        load(&mut loaded, &state).unwrap();
+        assert_eq!(loaded.state(), chip8.state());
+        assert_eq!(loaded.memory(), chip8.memory());
+        assert_eq!(loaded.get_video_buffer_as_rgba(), chip8.get_video_buffer_as_rgba());
+        assert_eq!(save(&loaded), state);
+    }
+
+    #[test]
+    fn bad_magic_is_rejected()
+    {
+        let mut state = save(&running_chip8());
+        state[0] = b'X';
+        assert!(matches!(load(&mut new_chip8(Quirks::cosmac_vip()), &state), Err(SaveStateError::NotASaveState)));
+        assert!(matches!(load(&mut new_chip8(Quirks::cosmac_vip()), b"CH8S"), Err(SaveStateError::NotASaveState)));
+    }
+
+    #[test]
+    fn other_versions_are_rejected()
+    {
+        let mut state = save(&running_chip8());
+        state[4 .. 6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(load(&mut new_chip8(Quirks::cosmac_vip()), &state), Err(SaveStateError::UnsupportedVersion(version)) if version == VERSION + 1));
+    }
+
+    #[test]
+    fn damaged_payloads_are_rejected()
+    {
+        let mut state = save(&running_chip8());
+        let middle = state.len() / 2;
+        state[middle] ^= 1;
+        assert!(matches!(load(&mut new_chip8(Quirks::cosmac_vip()), &state), Err(SaveStateError::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn states_of_other_roms_are_rejected()
+    {
+        let state = save(&running_chip8());
+        let mut other = Chip8::new(&[0x12, 0x00], Quirks::cosmac_vip(), RandomGenerator::seeded(7)).unwrap();
+        let before = other.state();
+        assert!(matches!(load(&mut other, &state), Err(SaveStateError::RomMismatch { .. })));
+        assert_eq!(other.state(), before);
+    }
+
+    //Offset in the payload of the first stack entry, behind the length of the stack
+    fn stack_offset(quirks : Quirks) -> usize
+    {
+        let mut writer = StateWriter::new();
+        quirks.write_state(&mut writer);
+        writer.u8(0);
+        RandomGenerator::seeded(7).write_state(&mut writer);
+        writer.u32(0);
+        writer.bytes(&vec![0; quirks.memory_size]);
+        //Registers, I, PC, timers and the stack length
+        writer.into_bytes().len() + 16 + 2 + 2 + 2 + 4
+    }
+
+    #[test]
+    fn stacks_deeper_than_the_quirks_are_rejected()
+    {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.stack_depth = None;
+        let mut chip8 = new_chip8(quirks);
+        chip8.set_stack(&[0x206; 13]).unwrap();
+        let state = save(&chip8);
+
+        //The stack depth is the u32 before the last bool of the quirks
+        let mut quirks_state = StateWriter::new();
+        quirks.write_state(&mut quirks_state);
+        let depth_offset = quirks_state.into_bytes().len() - 5;
+        let state = reframe(&state, &|payload| payload[depth_offset .. depth_offset + 4].copy_from_slice(&12u32.to_le_bytes()));
+        assert!(matches!(load(&mut new_chip8(Quirks::cosmac_vip()), &state), Err(SaveStateError::Corrupted(_))));
+    }
+
+    #[test]
+    fn stack_entries_outside_of_memory_are_rejected()
+    {
+        let mut chip8 = running_chip8();
+        assert_eq!(chip8.stack(), &[0x206]);
+        let state = save(&chip8);
+        let offset = stack_offset(Quirks::cosmac_vip());
+        assert_eq!(&state[HEADER_SIZE + offset .. HEADER_SIZE + offset + 2], &[0x06, 0x02]);
+
+        let state = reframe(&state, &|payload| payload[offset .. offset + 2].copy_from_slice(&0x0FFFu16.to_le_bytes()));
+        assert!(matches!(load(&mut chip8, &state), Err(SaveStateError::Corrupted(_))));
+        assert!(chip8.set_stack(&[0x0FFF]).is_err());
+        assert_eq!(chip8.stack(), &[0x206]);
+    }
+}
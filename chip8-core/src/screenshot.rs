@@ -0,0 +1,226 @@
+/*
+Writes the screen to image files without any image library.
+PNG files are made of uncompressed deflate blocks, which keeps the encoder tiny and is
+still a valid file for every reader. PBM files are black and white: pixels darker than
+middle gray are black.
+*/
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use chip8::Chip8;
+use savestate::crc32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat
+{
+    Png,
+    Pbm,
+}
+
+impl ImageFormat
+{
+    //From the extension of the path
+    #[allow(dead_code)]
+    pub fn from_path(path : &Path) -> Option<ImageFormat>
+    {
+        match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase())
+        {
+            Some(ref extension) if extension == "png" => Some(ImageFormat::Png),
+            Some(ref extension) if extension == "pbm" => Some(ImageFormat::Pbm),
+            _ => None,
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub fn save_screen(chip8 : &Chip8, path : &Path, format : ImageFormat) -> io::Result<()>
+{
+    let width = chip8.screen_width() as usize;
+    let height = chip8.screen_height() as usize;
+    //The video buffer is bottom-up for OpenGL
+    let flipped = chip8.get_video_buffer_as_rgba();
+    let mut rgba = Vec::with_capacity(flipped.len());
+    for row in (0 .. height).rev()
+    {
+        rgba.extend_from_slice(&flipped[row * width * 4 .. (row + 1) * width * 4]);
+    }
+
+    let data = match format
+    {
+        ImageFormat::Png => encode_png(width, height, &rgba),
+        ImageFormat::Pbm => encode_pbm(width, height, &rgba),
+    };
+    let mut file = File::create(path)?;
+    file.write_all(&data)
+}
+
+//RGBA rows from top to bottom
+pub fn encode_png(width : usize, height : usize, rgba : &[u8]) -> Vec<u8>
+{
+    //Each row starts with filter type 0
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in rgba.chunks(width * 4)
+    {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&u32_to_be(width as u32));
+    header.extend_from_slice(&u32_to_be(height as u32));
+    //8 bits per channel, RGBA, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_store(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+//Binary P4 PBM
+pub fn encode_pbm(width : usize, height : usize, rgba : &[u8]) -> Vec<u8>
+{
+    let mut pbm = format!("P4\n{} {}\n", width, height).into_bytes();
+    for row in rgba.chunks(width * 4)
+    {
+        let mut byte = 0u8;
+        for (x, pixel) in row.chunks(4).enumerate()
+        {
+            let luminance = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+            if luminance < 128
+            {
+                byte |= 0x80 >> (x % 8);
+            }
+            if x % 8 == 7 || x == width - 1
+            {
+                pbm.push(byte);
+                byte = 0;
+            }
+        }
+    }
+    pbm
+}
+
+fn write_chunk(png : &mut Vec<u8>, kind : &[u8; 4], data : &[u8])
+{
+    png.extend_from_slice(&u32_to_be(data.len() as u32));
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let checksum = crc32(&png[start ..]);
+    png.extend_from_slice(&u32_to_be(checksum));
+}
+
+//zlib stream of stored deflate blocks
+fn zlib_store(data : &[u8]) -> Vec<u8>
+{
+    const MAX_BLOCK : usize = 0xFFFF;
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none()
+    {
+        output.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next()
+    {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        output.push(if last { 1 } else { 0 });
+        output.extend_from_slice(&[length as u8, (length >> 8) as u8, !length as u8, (!length >> 8) as u8]);
+        output.extend_from_slice(block);
+    }
+    output.extend_from_slice(&u32_to_be(adler32(data)));
+    output
+}
+
+fn adler32(data : &[u8]) -> u32
+{
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data
+    {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn u32_to_be(value : u32) -> [u8; 4]
+{
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn adler32_of_known_data()
+    {
+        assert_eq!(adler32(&[]), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn empty_zlib_streams_have_one_empty_block()
+    {
+        assert_eq!(zlib_store(&[]), [0x78, 0x01, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn long_zlib_streams_are_split_in_blocks()
+    {
+        let data : Vec<u8> = (0 .. 70000).map(|i| i as u8).collect();
+        let stream = zlib_store(&data);
+        assert_eq!(stream.len(), 2 + 5 + 65535 + 5 + 4465 + 4);
+        assert_eq!(&stream[.. 7], [0x78, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(&stream[7 .. 7 + 65535], &data[.. 65535]);
+        //4465 bytes in the last block
+        assert_eq!(&stream[65542 .. 65547], [0x01, 0x71, 0x11, 0x8E, 0xEE]);
+        assert_eq!(&stream[65547 .. 70012], &data[65535 ..]);
+        assert_eq!(&stream[70012 ..], [0xA1, 0xAA, 0x17, 0xC1]);
+    }
+
+    #[test]
+    fn png_of_one_pixel()
+    {
+        let png = encode_png(1, 1, &[0xFF, 0x00, 0x80, 0xFF]);
+        assert_eq!(png, [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A,
+            //IHDR
+            0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            0x08, 0x06, 0x00, 0x00, 0x00, 0x1F, 0x15, 0xC4, 0x89,
+            //IDAT
+            0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x05, 0x00, 0xFA, 0xFF, 0x00,
+            0xFF, 0x00, 0x80, 0xFF, 0x06, 0x00, 0x02, 0x7F, 0x30, 0xCC, 0xE3, 0x04,
+            //IEND
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ]);
+    }
+
+    #[test]
+    fn pbm_rows_are_padded_to_whole_bytes()
+    {
+        const BLACK : [u8; 4] = [0, 0, 0, 0xFF];
+        const WHITE : [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+        //Just below and at middle gray
+        const DARK_GRAY : [u8; 4] = [127, 127, 127, 0xFF];
+        const LIGHT_GRAY : [u8; 4] = [128, 128, 128, 0xFF];
+        let mut rgba = Vec::new();
+        for x in 0 .. 10
+        {
+            rgba.extend_from_slice(match x { 0 => &BLACK, 1 => &DARK_GRAY, 2 => &LIGHT_GRAY, 9 => &BLACK, _ => &WHITE });
+        }
+        for _ in 0 .. 10
+        {
+            rgba.extend_from_slice(&BLACK);
+        }
+        let mut expected = b"P4\n10 2\n".to_vec();
+        expected.extend_from_slice(&[0xC0, 0x40, 0xFF, 0xC0]);
+        assert_eq!(encode_pbm(10, 2, &rgba), expected);
+    }
+}
@@ -0,0 +1,269 @@
+/*
+Execution trace, one entry per executed instruction with the machine state after it ran.
+The text format is meant to be diffed against the traces of other emulators:
+
+    cycle      PC  opcode mnemonic               V0-VF                            I    SP
+    0000000000 200 6005   LD V0, 0x05            05000000000000000000000000000000 000  0
+
+The binary format is the "CH8T" magic and a u16 version, followed by fixed size little
+endian records: cycle u64, PC u16, opcode u16, long address u16, V0-VF, I u16, SP u8.
+The long address is the second word of F000 NNNN and 0 after any other opcode. The header
+is written even when nothing gets traced. Version 1 records had no long address.
+*/
+
+use std::io;
+use std::io::prelude::*;
+use instruction::Instruction;
+use savestate::StateWriter;
+
+const TEXT_HEADER : &str = "cycle      PC  opcode mnemonic               V0-VF                            I    SP";
+const BINARY_MAGIC : &[u8; 4] = b"CH8T";
+const BINARY_VERSION : u16 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry
+{
+    //Instructions executed before this one
+    pub cycle : u64,
+    pub program_counter : u16,
+    pub opcode : u16,
+    //The second word of LD I, LONG
+    pub long_address : Option<u16>,
+    pub registers : [u8; 16],
+    pub address_register : u16,
+    pub stack_depth : u8,
+}
+
+pub trait TraceOutput
+{
+    fn write(&mut self, entry : &TraceEntry) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+pub struct TextTrace<W : Write>
+{
+    output : W,
+}
+
+impl<W : Write> TextTrace<W>
+{
+    //Writes the header line right away
+    #[allow(dead_code)]
+    pub fn new(mut output : W) -> io::Result<TextTrace<W>>
+    {
+        writeln!(output, "{}", TEXT_HEADER)?;
+        Ok(TextTrace { output })
+    }
+}
+
+impl<W : Write> TraceOutput for TextTrace<W>
+{
+    fn write(&mut self, entry : &TraceEntry) -> io::Result<()>
+    {
+        let mut registers = String::with_capacity(32);
+        for register in &entry.registers
+        {
+            registers.push_str(&format!("{:02X}", register));
+        }
+        let mnemonic = match entry.long_address
+        {
+            Some(address) => format!("LD I, LONG {:#06X}", address),
+            None => Instruction::decode(entry.opcode).to_string(),
+        };
+        writeln!(self.output, "{:010} {:03X} {:04X}   {:<22} {} {:03X} {:>2}",
+            entry.cycle, entry.program_counter, entry.opcode, mnemonic,
+            registers, entry.address_register, entry.stack_depth)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.output.flush()
+    }
+}
+
+pub struct BinaryTrace<W : Write>
+{
+    output : W,
+}
+
+impl<W : Write> BinaryTrace<W>
+{
+    //Writes the header right away
+    #[allow(dead_code)]
+    pub fn new(mut output : W) -> io::Result<BinaryTrace<W>>
+    {
+        let mut writer = StateWriter::new();
+        writer.bytes(BINARY_MAGIC);
+        writer.u16(BINARY_VERSION);
+        output.write_all(&writer.into_bytes())?;
+        Ok(BinaryTrace { output })
+    }
+}
+
+impl<W : Write> TraceOutput for BinaryTrace<W>
+{
+    fn write(&mut self, entry : &TraceEntry) -> io::Result<()>
+    {
+        let mut writer = StateWriter::new();
+        writer.u64(entry.cycle);
+        writer.u16(entry.program_counter);
+        writer.u16(entry.opcode);
+        writer.u16(entry.long_address.unwrap_or(0));
+        writer.bytes(&entry.registers);
+        writer.u16(entry.address_register);
+        writer.u8(entry.stack_depth);
+        self.output.write_all(&writer.into_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.output.flush()
+    }
+}
+
+//Entries have to pass both conditions to be written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceFilter
+{
+    //Inclusive range of program counters
+    pub addresses : Option<(u16, u16)>,
+    //Bit n set keeps the opcodes whose first nibble is n, so 0x0100 keeps the 8XYN instructions
+    pub opcode_classes : u16,
+}
+
+impl TraceFilter
+{
+    pub fn accepts(&self, program_counter : u16, opcode : u16) -> bool
+    {
+        let in_range = match self.addresses
+        {
+            Some((first, last)) => program_counter >= first && program_counter <= last,
+            None => true,
+        };
+        in_range && self.opcode_classes & (1 << (opcode >> 12)) != 0
+    }
+}
+
+impl Default for TraceFilter
+{
+    fn default() -> TraceFilter
+    {
+        TraceFilter { addresses : None, opcode_classes : 0xFFFF }
+    }
+}
+
+/*
+Attached to the interpreter with Chip8::set_tracer. A write error stops the tracing
+instead of the emulation, it is kept for the frontend to report.
+*/
+pub struct Tracer
+{
+    pub filter : TraceFilter,
+    output : Box<dyn TraceOutput>,
+    error : Option<io::Error>,
+}
+
+impl Tracer
+{
+    #[allow(dead_code)]
+    pub fn new(filter : TraceFilter, output : Box<dyn TraceOutput>) -> Tracer
+    {
+        Tracer { filter, output, error : None }
+    }
+
+    pub fn trace(&mut self, entry : &TraceEntry)
+    {
+        if self.error.is_some() || !self.filter.accepts(entry.program_counter, entry.opcode)
+        {
+            return;
+        }
+        if let Err(error) = self.output.write(entry)
+        {
+            self.error = Some(error);
+        }
+    }
+
+    //Flushes the output and returns the first error met while tracing
+    #[allow(dead_code)]
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        if let Some(error) = self.error.take()
+        {
+            return Err(error);
+        }
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use chip8::Chip8;
+    use quirks::Quirks;
+    use random::RandomGenerator;
+
+    struct Collector(Rc<RefCell<Vec<TraceEntry>>>);
+
+    impl TraceOutput for Collector
+    {
+        fn write(&mut self, entry : &TraceEntry) -> io::Result<()>
+        {
+            self.0.borrow_mut().push(*entry);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    fn entry(opcode : u16, long_address : Option<u16>) -> TraceEntry
+    {
+        TraceEntry { cycle : 0, program_counter : 0x200, opcode, long_address, registers : [0; 16], address_register : 0, stack_depth : 0 }
+    }
+
+    #[test]
+    fn text_traces_start_with_the_header()
+    {
+        let mut output = Vec::new();
+        {
+            let mut trace = TextTrace::new(&mut output).unwrap();
+            trace.write(&entry(0x6005, None)).unwrap();
+        }
+        let text = String::from_utf8(output).unwrap();
+        let lines : Vec<&str> = text.lines().collect();
+        assert_eq!(lines, [TEXT_HEADER, "0000000000 200 6005   LD V0, 0x05            00000000000000000000000000000000 000  0"]);
+    }
+
+    #[test]
+    fn empty_binary_traces_have_a_header()
+    {
+        let mut output = Vec::new();
+        BinaryTrace::new(&mut output).unwrap();
+        assert_eq!(output, b"CH8T\x02\x00");
+    }
+
+    #[test]
+    fn long_addresses_are_traced()
+    {
+        let entries = Rc::new(RefCell::new(Vec::new()));
+        let mut chip8 = Chip8::new(&[0xF0, 0x00, 0x12, 0x34], Quirks::xo_chip(), RandomGenerator::seeded(0)).unwrap();
+        chip8.set_tracer(Some(Tracer::new(TraceFilter::default(), Box::new(Collector(entries.clone())))));
+        chip8.run_one_cycle().unwrap();
+        let entry = entries.borrow()[0];
+        assert_eq!(entry.long_address, Some(0x1234));
+
+        let mut output = Vec::new();
+        TextTrace::new(&mut output).unwrap().write(&entry).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains(" F000   LD I, LONG 0x1234 "));
+
+        let mut output = Vec::new();
+        BinaryTrace::new(&mut output).unwrap().write(&entry).unwrap();
+        assert_eq!(output.len(), 6 + 8 + 2 + 2 + 2 + 16 + 2 + 1);
+        assert_eq!(&output[14 .. 20], [0x00, 0x02, 0x00, 0xF0, 0x34, 0x12]);
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(feature = "window")]
+#[macro_use]
+extern crate glium;
+extern crate chip8_core;
+extern crate chip8_tools;
+
+#[cfg(feature = "window")]
+mod window;
+
+use chip8_tools::cli;
+
+#[cfg(feature = "window")]
+fn run_window(options : cli::RunOptions) -> Result<(), String>
+{
+    window::run_window(options)
+}
+
+#[cfg(not(feature = "window"))]
+fn run_window(_options : cli::RunOptions) -> Result<(), String>
+{
+    Err("This build has no window, add --headless or build with the window feature".to_string())
+}
+
+//Same command line as chip8-tools, plus the runs in a window
+fn main()
+{
+    let result = match cli::parse_args_or_exit()
+    {
+        cli::Command::Run(options) => match options.headless
+        {
+            None => run_window(options),
+            Some(_) => chip8_tools::run(cli::Command::Run(options)),
+        },
+        command => chip8_tools::run(command),
+    };
+    if let Err(error) = result
+    {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,283 @@
+/*
+Window of the desktop frontend: the screen drawn with OpenGL, the keypad read from the
+keyboard through the keymap, and the hotkeys
+    Tab         fast-forward while held
+    Backspace   rewind while held
+    F5 / F9     save / load the current slot
+    F6 / F7     previous / next slot
+*/
+
+use glium::glutin;
+use glium::glutin::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
+use glium::glutin::event_loop::{ControlFlow, EventLoop};
+use glium::glutin::platform::run_return::EventLoopExtRunReturn;
+use glium::index::PrimitiveType;
+use glium::Surface;
+use std::time::Instant;
+use chip8_core::{audio, movie, rewind, savestate};
+use chip8_tools::cli;
+use chip8_tools::session::{Session, start_session, finish_trace};
+
+//The sound card with the audio feature, silence otherwise
+#[cfg(feature = "audio")]
+fn open_audio_output() -> Box<dyn audio::AudioOutput>
+{
+    match audio::DeviceOutput::open()
+    {
+        Ok(output) => Box::new(output),
+        Err(error) =>
+        {
+            eprintln!("Sound disabled: {}", error);
+            Box::new(audio::NullOutput::new(audio::DEFAULT_SAMPLE_RATE))
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_audio_output() -> Box<dyn audio::AudioOutput>
+{
+    Box::new(audio::NullOutput::new(audio::DEFAULT_SAMPLE_RATE))
+}
+
+//A failing sound card mutes the emulator rather than stopping it
+fn check_audio(beeper : &mut audio::Beeper, result : ::std::io::Result<()>)
+{
+    if let Err(error) = result
+    {
+        eprintln!("Sound disabled: {}", error);
+        beeper.set_output(Box::new(audio::NullOutput::new(audio::DEFAULT_SAMPLE_RATE)));
+    }
+}
+
+pub fn run_window(options : cli::RunOptions) -> Result<(), String>
+{
+    let Session { mut chip8, mut clock, seed, mut player } = start_session(&options)?;
+    let mut recorder = options.record.as_ref().map(|path| (movie::Recorder::new(&chip8, seed, clock.instructions_per_frame), path.clone()));
+    //Rewinding and loading states would break the movie
+    let has_movie = player.is_some() || recorder.is_some();
+
+    let (width, height) = (64 * options.scale, 32 * options.scale);
+    let mut event_loop = EventLoop::new();
+    let window = glutin::window::WindowBuilder::new().with_inner_size(glutin::dpi::LogicalSize::new(width, height)).with_title(String::from("Chip8 Emulator"));
+    let context = glutin::ContextBuilder::new().with_vsync(true);
+    let display = glium::Display::new(window, context, &event_loop).map_err(|error| format!("Cannot open the window: {}", error))?;
+
+    let vertex_buffer =
+    {
+        #[derive(Copy, Clone)]
+        struct Vertex
+        {
+            position: [f32; 2],
+            tex_coords: [f32; 2],
+        }
+
+        implement_vertex!(Vertex, position, tex_coords);
+
+        glium::VertexBuffer::new(&display,
+            &[
+                Vertex { position: [-1.0, -1.0], tex_coords: [0.0, 0.0] },
+                Vertex { position: [-1.0,  1.0], tex_coords: [0.0, 1.0] },
+                Vertex { position: [ 1.0,  1.0], tex_coords: [1.0, 1.0] },
+                Vertex { position: [ 1.0, -1.0], tex_coords: [1.0, 0.0] }
+            ]
+        ).unwrap()
+    };
+
+    let index_buffer = glium::IndexBuffer::new(&display, PrimitiveType::TriangleStrip, &[1u16, 2, 0, 3]).unwrap();
+    let program = glium::Program::from_source(&display,
+
+                "#version 140
+                uniform mat4 matrix;
+                in vec2 position;
+                in vec2 tex_coords;
+                out vec2 v_tex_coords;
+                void main() {
+                    gl_Position = matrix * vec4(position, 0.0, 1.0);
+                    v_tex_coords = tex_coords;
+                }",
+
+                "#version 140
+                uniform sampler2D tex;
+                in vec2 v_tex_coords;
+                out vec4 f_color;
+                void main() {
+                    f_color = texture(tex, v_tex_coords);
+                }",
+            None
+    ).unwrap();
+
+    let mut frame_count = 0;
+    let mut has_failed = false;
+    let audio_output = open_audio_output();
+    let mut beeper = audio::Beeper::new(audio::SquareWave::default(), audio_output);
+    let mut last_frame_time = Instant::now();
+    let mut save_slot = options.load_slot.unwrap_or(0);
+    let mut rewind = rewind::Rewind::default();
+    let mut rewinding = false;
+    let mut result = Ok(());
+    event_loop.run_return(|event, _, control_flow|
+    {
+        *control_flow = ControlFlow::Poll;
+        match event
+        {
+            Event::WindowEvent { event : WindowEvent::CloseRequested, .. } =>
+            {
+                if let Some((recorder, path)) = recorder.take()
+                {
+                    match recorder.finish().save_to_file(&path)
+                    {
+                        Ok(()) => println!("Movie written to {}", path.display()),
+                        Err(error) => eprintln!("Cannot write the movie {}: {}", path.display(), error),
+                    }
+                }
+                result = finish_trace(&mut chip8);
+                control_flow.set_exit();
+                return;
+            }
+            Event::WindowEvent { event : WindowEvent::KeyboardInput { input, .. }, .. } =>
+            {
+                match (input.state, input.virtual_keycode)
+                {
+                    //Holding tab fast-forwards
+                    (state, Some(VirtualKeyCode::Tab)) =>
+                    {
+                        clock.speed = match state
+                        {
+                            ElementState::Pressed => 4.0,
+                            ElementState::Released => 1.0
+                        };
+                    }
+                    //Holding backspace plays backwards
+                    (state, Some(VirtualKeyCode::Back)) if !has_movie =>
+                    {
+                        rewinding = state == ElementState::Pressed;
+                    }
+                    //F5 saves to the current slot, F9 loads it, F6 and F7 change slot
+                    (ElementState::Pressed, Some(VirtualKeyCode::F5)) =>
+                    {
+                        match savestate::save_to_file(&chip8, &savestate::slot_path(&options.rom, save_slot))
+                        {
+                            Ok(()) => println!("Saved state to slot {}", save_slot),
+                            Err(error) => eprintln!("Cannot save slot {}: {}", save_slot, error),
+                        }
+                    }
+                    (ElementState::Pressed, Some(VirtualKeyCode::F9)) if !has_movie =>
+                    {
+                        match savestate::load_from_file(&mut chip8, &savestate::slot_path(&options.rom, save_slot))
+                        {
+                            Ok(()) =>
+                            {
+                                println!("Loaded state from slot {}", save_slot);
+                                has_failed = false;
+                                rewind.clear();
+                            }
+                            Err(error) => eprintln!("Cannot load slot {}: {}", save_slot, error),
+                        }
+                    }
+                    (ElementState::Pressed, Some(VirtualKeyCode::F6)) =>
+                    {
+                        save_slot = (save_slot + savestate::SLOTS - 1) % savestate::SLOTS;
+                        println!("Save slot {}", save_slot);
+                    }
+                    (ElementState::Pressed, Some(VirtualKeyCode::F7)) =>
+                    {
+                        save_slot = (save_slot + 1) % savestate::SLOTS;
+                        println!("Save slot {}", save_slot);
+                    }
+                    //The keypad belongs to the movie until it ends
+                    _ if player.as_ref().is_some_and(|player| !player.is_finished()) => (),
+                    (state, virtual_key) =>
+                    {
+                        let virtual_key_name = virtual_key.map(|virtual_key| format!("{:?}", virtual_key));
+                        if let Some(key) = options.keymap.key_for(input.scancode, virtual_key_name.as_deref())
+                        {
+                            match state
+                            {
+                                ElementState::Pressed => chip8.press_key(key),
+                                ElementState::Released => chip8.release_key(key)
+                            }
+                        }
+                    }
+                }
+                return;
+            }
+            //The emulation runs once the pending events are handled
+            Event::MainEventsCleared => (),
+            _ => return,
+        }
+
+        let now = Instant::now();
+        let elapsed = now - last_frame_time;
+        last_frame_time = now;
+
+        let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        if rewinding
+        {
+            for _ in 0 .. clock.frames_for(elapsed_seconds)
+            {
+                match rewind.step_back(&mut chip8)
+                {
+                    Ok(true) => has_failed = false,
+                    Ok(false) => (),
+                    Err(error) =>
+                    {
+                        println!("Cannot rewind: {}", error);
+                        rewinding = false;
+                        break;
+                    }
+                }
+                let result = beeper.play_frame(false);
+                check_audio(&mut beeper, result);
+            }
+        }
+        else if !has_failed
+        {
+            for _ in 0 .. clock.frames_for(elapsed_seconds)
+            {
+                rewind.record(&chip8);
+                if let Some((ref mut recorder, _)) = recorder
+                {
+                    recorder.record(&chip8);
+                }
+                if let Some(ref mut player) = player
+                {
+                    player.apply(&mut chip8);
+                }
+                if let Err(error) = chip8.run_frame(clock.instructions_per_frame)
+                {
+                    println!("Emulation stopped: {}", error);
+                    has_failed = true;
+                    break;
+                }
+                let result = beeper.play_chip8_frame(&chip8);
+                check_audio(&mut beeper, result);
+            }
+        }
+
+        let image = glium::texture::RawImage2d::from_raw_rgba(chip8.get_video_buffer_as_rgba(), (chip8.screen_width(), chip8.screen_height()) );
+        let opengl_texture = glium::texture::SrgbTexture2d ::new(&display, image).unwrap();
+
+        let uniforms = uniform! {
+            matrix: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0f32]
+            ],
+            tex: glium::uniforms::Sampler::new(&opengl_texture)
+                        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+        };
+
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 0.0);
+        target.draw(&vertex_buffer, &index_buffer, &program, &uniforms, &Default::default()).unwrap();
+        target.finish().unwrap();
+
+
+
+
+
+        frame_count += 1;
+    });
+    result
+}
@@ -0,0 +1,677 @@
+/*
+Command line of the emulator. Options can be given in any order after the subcommand and
+the subcommand can be left out to run a rom, so "chip8 game.ch8 --scale 5" works.
+Everything is checked here so the frontend only deals with valid values.
+*/
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use chip8_core::chip8::{Palette, DEFAULT_PALETTE};
+use chip8_core::clock;
+use chip8_core::quirks::{self, Quirks};
+use chip8_core::savestate;
+use chip8_core::screenshot::ImageFormat;
+use chip8_core::trace::TraceFilter;
+use gdb_stub;
+use keymap::{self, Keymap};
+
+pub const USAGE : &str = "\
+Usage:
+    chip8 [run] <rom> [options]         play a rom
+    chip8 info <rom>                    show the size, checksum and platform of a rom
+    chip8 disasm <rom>                  disassemble a rom
+    chip8 asm <source> <output>         assemble a source file
+    chip8 debug <rom> [options]         debug a rom from the console
+    chip8 gdb <rom> [--port <n>] [options]
+                                        debug a rom with gdb, on port 1234 by default
+    chip8 dap                           debug adapter for editors, on stdin and stdout
+    chip8 help                          show this help
+
+Options:
+    --speed <n>                 instructions per second, 600 by default
+    --scale <n>                 window pixels per CHIP-8 pixel, 10 by default
+    --quirks <preset>           vip, chip48, schip or xo-chip, vip by default
+    --palette <colors>          2 or 4 colors like 000000,FFFFFF
+    --seed <n>                  seed of the random numbers, random by default
+    --vip-random <file>         random numbers of the COSMAC VIP, from its interpreter page
+                                0x100-0x1FF or the whole 512 bytes interpreter
+    --keymap <preset or file>   qwerty, azerty, numpad or a keymap file, qwerty by default
+    --load-slot <n>             load the save state of slot 0 to 9 on start
+    --record <movie>            record the input, movie.txt for the text format
+    --play <movie>              replay the input of a movie
+    --trace <path>              write a trace of every executed instruction
+    --trace-format <format>     text or binary, text by default
+    --trace-range <first-last>  only trace these addresses, like 200-2FF
+    --trace-opcodes <classes>   only trace opcodes starting with these digits, like 0,8,D
+
+Headless options:
+    --headless                  run without a window nor sound card
+    --frames <n>                stop after n frames, 600 by default
+    --instructions <n>          stop after n instructions
+    --every <n>                 also save the screen every n frames to numbered files
+    --output <path>             final screen, a .png or .pbm file, screen.png by default
+    --wav <path>                write the sound to a WAV file
+";
+
+const MIN_INSTRUCTIONS_PER_SECOND : u32 = 60;
+const MAX_INSTRUCTIONS_PER_SECOND : u32 = 1_000_000;
+const MAX_SCALE : u32 = 20;
+
+//Every option but --headless takes a value
+const OPTIONS : [&str; 20] =
+[
+    "--speed", "--scale", "--quirks", "--palette", "--seed", "--vip-random", "--keymap", "--load-slot", "--record", "--play",
+    "--trace", "--trace-format", "--trace-range", "--trace-opcodes", "--frames", "--instructions", "--every", "--output", "--wav",
+    "--port",
+];
+
+#[derive(Debug)]
+pub enum Command
+{
+    Run(RunOptions),
+    Info(PathBuf),
+    Disassemble(PathBuf),
+    Assemble { source : PathBuf, output : PathBuf },
+    Debug(RunOptions),
+    Gdb { options : RunOptions, port : u16 },
+    Dap,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct RunOptions
+{
+    pub rom : PathBuf,
+    pub instructions_per_frame : u32,
+    pub scale : u32,
+    pub quirks : Quirks,
+    pub palette : Palette,
+    //None for a random seed
+    pub seed : Option<u64>,
+    //The COSMAC VIP generator with this interpreter page instead of the seeded one
+    pub vip_random_page : Option<[u8; 256]>,
+    pub keymap : Keymap,
+    pub load_slot : Option<u32>,
+    pub record : Option<PathBuf>,
+    pub play : Option<PathBuf>,
+    pub trace : Option<TraceOptions>,
+    //None to open a window
+    pub headless : Option<HeadlessOptions>,
+}
+
+#[derive(Debug)]
+pub struct TraceOptions
+{
+    pub path : PathBuf,
+    pub binary : bool,
+    pub filter : TraceFilter,
+}
+
+#[derive(Debug)]
+pub struct HeadlessOptions
+{
+    //Both None runs for the length of the movie being played, or 600 frames
+    pub frames : Option<u64>,
+    pub instructions : Option<u64>,
+    pub every : Option<u64>,
+    pub output : PathBuf,
+    pub format : ImageFormat,
+    pub wav : Option<PathBuf>,
+}
+
+//args without the name of the program
+#[allow(dead_code)]
+pub fn parse(args : &[String]) -> Result<Command, String>
+{
+    let rest = args.get(1 ..).unwrap_or(&[]);
+    match args.first().map(|command| &**command)
+    {
+        None => Err("no rom given".to_string()),
+        Some("help") | Some("--help") | Some("-h") => Ok(Command::Help),
+        Some("dap") if rest.is_empty() => Ok(Command::Dap),
+        Some("info") => Ok(Command::Info(single_path(rest, "info", "rom")?)),
+        Some("disasm") => Ok(Command::Disassemble(single_path(rest, "disasm", "rom")?)),
+        Some("asm") if rest.len() == 2 => Ok(Command::Assemble { source : PathBuf::from(&rest[0]), output : PathBuf::from(&rest[1]) }),
+        Some("asm") => Err("asm expects the path of the source then the path of the output".to_string()),
+        Some("run") => Ok(Command::Run(parse_run_options(rest, false)?.0)),
+        Some("debug") => Ok(Command::Debug(parse_run_options(rest, false)?.0)),
+        Some("gdb") =>
+        {
+            let (options, port) = parse_run_options(rest, true)?;
+            Ok(Command::Gdb { options, port : port.unwrap_or(gdb_stub::DEFAULT_PORT) })
+        }
+        Some("dap") => Err("dap does not take arguments, the rom is given by the editor".to_string()),
+        Some(_) => Ok(Command::Run(parse_run_options(args, false)?.0)),
+    }
+}
+
+//Exits with the usage or the error when the command line is not valid
+#[allow(dead_code)]
+pub fn parse_args_or_exit() -> Command
+{
+    let args : Vec<String> = env::args().skip(1).collect();
+    match parse(&args)
+    {
+        Ok(command) => command,
+        //The whole usage for no argument at all, the error alone otherwise
+        Err(_) if args.is_empty() =>
+        {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+        Err(error) =>
+        {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
+
+fn single_path(args : &[String], command : &str, what : &str) -> Result<PathBuf, String>
+{
+    match args.len()
+    {
+        1 => Ok(PathBuf::from(&args[0])),
+        0 => Err(format!("{} expects the path of a {}", command, what)),
+        _ => Err(format!("{} expects only the path of a {}, not '{}'", command, what, args[1])),
+    }
+}
+
+fn parse_run_options(args : &[String], accepts_port : bool) -> Result<(RunOptions, Option<u16>), String>
+{
+    let mut rom = None;
+    let mut port = None;
+    let mut instructions_per_frame = clock::DEFAULT_INSTRUCTIONS_PER_FRAME;
+    let mut scale = 10;
+    let mut quirks = Quirks::cosmac_vip();
+    let mut palette = DEFAULT_PALETTE;
+    let mut seed = None;
+    let mut vip_random_page = None;
+    let mut keymap = Keymap::default();
+    let mut load_slot = None;
+    let mut record = None;
+    let mut play = None;
+    let mut trace_path = None;
+    let mut trace_binary = false;
+    let mut trace_filter = TraceFilter::default();
+    let mut has_trace_option = false;
+    let mut headless = false;
+    let mut frames = None;
+    let mut instructions = None;
+    let mut every = None;
+    let mut output = None;
+    let mut wav = None;
+    let mut has_headless_option = false;
+
+    let mut i = 0;
+    while i < args.len()
+    {
+        let option = &*args[i];
+        if !option.starts_with("--")
+        {
+            if rom.is_some()
+            {
+                return Err(format!("unexpected argument '{}', only one rom can be given", option));
+            }
+            rom = Some(PathBuf::from(option));
+            i += 1;
+            continue;
+        }
+        if option == "--headless"
+        {
+            headless = true;
+            i += 1;
+            continue;
+        }
+
+        if !OPTIONS.contains(&option) || (option == "--port" && !accepts_port)
+        {
+            return Err(format!("unknown option {}, chip8 help lists them", option));
+        }
+        let value : &str = args.get(i + 1).ok_or(format!("{} needs a value", option))?;
+        match option
+        {
+            "--speed" =>
+            {
+                let speed = parse_number(option, value)?;
+                if speed < MIN_INSTRUCTIONS_PER_SECOND as u64 || speed > MAX_INSTRUCTIONS_PER_SECOND as u64
+                {
+                    return Err(format!("--speed is in instructions per second and goes from {} to {}, not {}", MIN_INSTRUCTIONS_PER_SECOND, MAX_INSTRUCTIONS_PER_SECOND, speed));
+                }
+                //The interpreter runs whole frames
+                instructions_per_frame = ((speed as f64 / clock::FRAMES_PER_SECOND).round() as u32).max(1);
+            }
+            "--scale" =>
+            {
+                scale = match u32::try_from(parse_number(option, value)?)
+                {
+                    Ok(scale) if scale > 0 && scale <= MAX_SCALE => scale,
+                    _ => return Err(format!("--scale goes from 1 to {}, not {}", MAX_SCALE, value)),
+                };
+            }
+            "--quirks" => quirks = Quirks::from_name(value).ok_or(format!("unknown quirk preset '{}', the presets are {}", value, quirks::PRESET_NAMES.join(", ")))?,
+            "--palette" => palette = parse_palette(value)?,
+            "--seed" => seed = Some(parse_number(option, value)?),
+            "--vip-random" => vip_random_page = Some(read_interpreter_page(value)?),
+            "--keymap" =>
+            {
+                keymap = match Keymap::from_preset(value)
+                {
+                    Some(preset) => preset,
+                    None => Keymap::load_from_file(Path::new(value)).map_err(|error| format!("{}, the keymap presets are {}", error, keymap::PRESETS.join(", ")))?,
+                };
+            }
+            "--load-slot" =>
+            {
+                let slot = parse_number(option, value)?;
+                if slot >= savestate::SLOTS as u64
+                {
+                    return Err(format!("--load-slot goes from 0 to {}, not {}", savestate::SLOTS - 1, slot));
+                }
+                load_slot = Some(slot as u32);
+            }
+            "--record" => record = Some(PathBuf::from(value)),
+            "--play" => play = Some(PathBuf::from(value)),
+            "--trace" => trace_path = Some(PathBuf::from(value)),
+            "--trace-format" =>
+            {
+                has_trace_option = true;
+                trace_binary = match value
+                {
+                    "text" => false,
+                    "binary" => true,
+                    _ => return Err(format!("--trace-format is text or binary, not '{}'", value)),
+                };
+            }
+            "--trace-range" =>
+            {
+                has_trace_option = true;
+                trace_filter.addresses = Some(parse_address_range(value)?);
+            }
+            "--trace-opcodes" =>
+            {
+                has_trace_option = true;
+                trace_filter.opcode_classes = parse_opcode_classes(value)?;
+            }
+            "--frames" =>
+            {
+                has_headless_option = true;
+                frames = Some(parse_number(option, value)?);
+            }
+            "--instructions" =>
+            {
+                has_headless_option = true;
+                instructions = Some(parse_number(option, value)?);
+            }
+            "--every" =>
+            {
+                has_headless_option = true;
+                let frames = parse_number(option, value)?;
+                if frames == 0
+                {
+                    return Err("--every needs at least 1 frame".to_string());
+                }
+                every = Some(frames);
+            }
+            "--output" =>
+            {
+                has_headless_option = true;
+                output = Some(PathBuf::from(value));
+            }
+            "--wav" =>
+            {
+                has_headless_option = true;
+                wav = Some(PathBuf::from(value));
+            }
+            "--port" =>
+            {
+                let number = parse_number(option, value)?;
+                if number == 0 || number > 0xFFFF
+                {
+                    return Err(format!("--port goes from 1 to 65535, not {}", number));
+                }
+                port = Some(number as u16);
+            }
+            _ => unreachable!(),
+        }
+        i += 2;
+    }
+
+    let rom = rom.ok_or("no rom given".to_string())?;
+    if has_trace_option && trace_path.is_none()
+    {
+        return Err("--trace-format, --trace-range and --trace-opcodes need --trace <path>".to_string());
+    }
+    if has_headless_option && !headless
+    {
+        return Err("--frames, --instructions, --every, --output and --wav need --headless".to_string());
+    }
+    if frames.is_some() && instructions.is_some()
+    {
+        return Err("--frames and --instructions cannot be used together".to_string());
+    }
+    if record.is_some() && play.is_some()
+    {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+    if record.is_some() && headless
+    {
+        return Err("--record needs a window to take the input from".to_string());
+    }
+    if vip_random_page.is_some() && seed.is_some()
+    {
+        return Err("--seed and --vip-random cannot be used together".to_string());
+    }
+    //Movies only keep the seed of the random numbers
+    if vip_random_page.is_some() && (record.is_some() || play.is_some())
+    {
+        return Err("--vip-random cannot be used with --record or --play".to_string());
+    }
+    //A movie starts from the power on state
+    if load_slot.is_some() && (record.is_some() || play.is_some())
+    {
+        return Err("--load-slot cannot be used with --record or --play".to_string());
+    }
+
+    let headless = if headless
+    {
+        let output = output.unwrap_or(PathBuf::from("screen.png"));
+        let format = ImageFormat::from_path(&output).ok_or(format!("{} should end with .png or .pbm", output.display()))?;
+        Some(HeadlessOptions { frames, instructions, every, output, format, wav })
+    }
+    else
+    {
+        None
+    };
+
+    let options = RunOptions
+    {
+        rom,
+        instructions_per_frame,
+        scale,
+        quirks,
+        palette,
+        seed,
+        vip_random_page,
+        keymap,
+        load_slot,
+        record,
+        play,
+        trace : trace_path.map(|path| TraceOptions { path, binary : trace_binary, filter : trace_filter }),
+        headless,
+    };
+    Ok((options, port))
+}
+
+//The page 0x100-0x1FF of the VIP interpreter, alone or in a dump of the whole interpreter
+fn read_interpreter_page(path : &str) -> Result<[u8; 256], String>
+{
+    let data = fs::read(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    let page = match data.len()
+    {
+        256 => &data[..],
+        512 => &data[256 ..],
+        length => return Err(format!("--vip-random expects 256 or 512 bytes, {} has {}", path, length)),
+    };
+    let mut interpreter_page = [0; 256];
+    interpreter_page.copy_from_slice(page);
+    Ok(interpreter_page)
+}
+
+//Decimal or hexadecimal with 0x
+fn parse_number(option : &str, value : &str) -> Result<u64, String>
+{
+    let parsed = match value.strip_prefix("0x")
+    {
+        Some(digits) => u64::from_str_radix(digits, 16),
+        None => value.parse::<u64>(),
+    };
+    parsed.map_err(|_| format!("{} expects a number, not '{}'", option, value))
+}
+
+//RRGGBB colors separated by commas, with an optional #. Two colors only replace the first two
+fn parse_palette(value : &str) -> Result<Palette, String>
+{
+    let colors : Vec<&str> = value.split(',').map(|color| color.trim().trim_start_matches('#')).collect();
+    if colors.len() != 2 && colors.len() != 4
+    {
+        return Err(format!("--palette expects 2 or 4 colors like 000000,FFFFFF, not '{}'", value));
+    }
+    let mut palette = DEFAULT_PALETTE;
+    for (i, color) in colors.iter().enumerate()
+    {
+        let rgb = match u32::from_str_radix(color, 16)
+        {
+            Ok(rgb) if color.len() == 6 => rgb,
+            _ => return Err(format!("'{}' is not a color, colors are written RRGGBB", color)),
+        };
+        palette[i] = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255];
+    }
+    Ok(palette)
+}
+
+fn parse_address_range(value : &str) -> Result<(u16, u16), String>
+{
+    let parse_address = |text : &str| u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("'{}' is not an hexadecimal address", text));
+    let mut bounds = value.splitn(2, '-');
+    let first = parse_address(bounds.next().unwrap())?;
+    let last = parse_address(bounds.next().ok_or("the range looks like 200-2FF".to_string())?)?;
+    if first > last
+    {
+        return Err(format!("the range {} ends before it starts", value));
+    }
+    Ok((first, last))
+}
+
+fn parse_opcode_classes(value : &str) -> Result<u16, String>
+{
+    let mut classes = 0;
+    for class in value.split(',')
+    {
+        match u8::from_str_radix(class, 16)
+        {
+            Ok(nibble) if nibble < 16 && class.len() == 1 => classes |= 1 << nibble,
+            _ => return Err(format!("'{}' is not an opcode class, they go from 0 to F", class)),
+        }
+    }
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse_words(words : &str) -> Result<Command, String>
+    {
+        parse(&words.split_whitespace().map(|word| word.to_string()).collect::<Vec<_>>())
+    }
+
+    fn run_options(words : &str) -> RunOptions
+    {
+        match parse_words(words)
+        {
+            Ok(Command::Run(options)) => options,
+            result => panic!("{}: {:?}", words, result),
+        }
+    }
+
+    fn error(words : &str) -> String
+    {
+        match parse_words(words)
+        {
+            Err(error) => error,
+            result => panic!("{}: {:?}", words, result),
+        }
+    }
+
+    #[test]
+    fn scale_is_checked()
+    {
+        match parse_words("game.ch8 --scale 5")
+        {
+            Ok(Command::Run(options)) => assert_eq!(options.scale, 5),
+            result => panic!("{:?}", result.err()),
+        }
+        assert!(parse_words("game.ch8 --scale 0").is_err());
+        assert!(parse_words("game.ch8 --scale 21").is_err());
+        //Would be 5 once truncated to 32 bits
+        assert!(parse_words("game.ch8 --scale 4294967301").is_err());
+    }
+
+    #[test]
+    fn every_needs_a_frame()
+    {
+        match parse_words("run game.ch8 --headless --every 60")
+        {
+            Ok(Command::Run(options)) => assert_eq!(options.headless.unwrap().every, Some(60)),
+            result => panic!("{:?}", result.err()),
+        }
+        assert!(parse_words("run game.ch8 --headless --every 0").is_err());
+    }
+
+    #[test]
+    fn vip_random_takes_the_interpreter_page()
+    {
+        let path = ::std::env::temp_dir().join("cli_vip_interpreter.bin");
+        let mut interpreter = vec![0; 512];
+        interpreter[0x100] = 0x42;
+        fs::write(&path, &interpreter).unwrap();
+        let option = format!("game.ch8 --vip-random {}", path.display());
+        match parse_words(&option)
+        {
+            Ok(Command::Run(options)) => assert_eq!(options.vip_random_page.unwrap()[0], 0x42),
+            result => panic!("{:?}", result.err()),
+        }
+        assert!(parse_words(&format!("{} --seed 1", option)).is_err());
+        fs::write(&path, &interpreter[.. 100]).unwrap();
+        assert!(parse_words(&option).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn speed_is_rounded_to_instructions_per_frame()
+    {
+        assert_eq!(run_options("game.ch8").instructions_per_frame, clock::DEFAULT_INSTRUCTIONS_PER_FRAME);
+        assert_eq!(run_options("game.ch8 --speed 600").instructions_per_frame, 10);
+        assert_eq!(run_options("game.ch8 --speed 60").instructions_per_frame, 1);
+        assert_eq!(run_options("game.ch8 --speed 90").instructions_per_frame, 2);
+        assert_eq!(run_options("game.ch8 --speed 1000").instructions_per_frame, 17);
+        assert_eq!(run_options("game.ch8 --speed 1000000").instructions_per_frame, 16667);
+        assert!(error("game.ch8 --speed 59").starts_with("--speed"));
+        assert!(error("game.ch8 --speed 1000001").starts_with("--speed"));
+        assert_eq!(error("game.ch8 --speed fast"), "--speed expects a number, not 'fast'");
+        assert_eq!(error("game.ch8 --speed"), "--speed needs a value");
+    }
+
+    #[test]
+    fn palettes_have_2_or_4_colors()
+    {
+        let palette = run_options("game.ch8 --palette 102030,#A0B0C0").palette;
+        assert_eq!(palette, [[0x10, 0x20, 0x30, 255], [0xA0, 0xB0, 0xC0, 255], DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]]);
+        let palette = run_options("game.ch8 --palette 000001,000002,000003,000004").palette;
+        assert_eq!(palette, [[0, 0, 1, 255], [0, 0, 2, 255], [0, 0, 3, 255], [0, 0, 4, 255]]);
+        assert!(error("game.ch8 --palette 000000").starts_with("--palette expects 2 or 4 colors"));
+        assert!(error("game.ch8 --palette 000000,111111,222222").starts_with("--palette expects 2 or 4 colors"));
+        assert_eq!(error("game.ch8 --palette 000000,FFF"), "'FFF' is not a color, colors are written RRGGBB");
+        assert_eq!(error("game.ch8 --palette 000000,GGGGGG"), "'GGGGGG' is not a color, colors are written RRGGBB");
+    }
+
+    #[test]
+    fn trace_options_need_trace()
+    {
+        let trace = run_options("game.ch8 --trace out.bin --trace-format binary --trace-range 200-2FF --trace-opcodes 0,D").trace.unwrap();
+        assert_eq!(trace.path, PathBuf::from("out.bin"));
+        assert!(trace.binary);
+        assert_eq!(trace.filter.addresses, Some((0x200, 0x2FF)));
+        assert_eq!(trace.filter.opcode_classes, 0x2001);
+        for option in &["--trace-format text", "--trace-range 200-2FF", "--trace-opcodes 8"]
+        {
+            assert!(error(&format!("game.ch8 {}", option)).ends_with("need --trace <path>"), "{}", option);
+        }
+        assert!(error("game.ch8 --trace out.txt --trace-range 2FF-200").ends_with("ends before it starts"));
+        assert!(error("game.ch8 --trace out.txt --trace-opcodes 10").starts_with("'10' is not an opcode class"));
+    }
+
+    #[test]
+    fn headless_options_need_headless()
+    {
+        for option in &["--frames 10", "--instructions 10", "--every 10", "--output screen.pbm", "--wav sound.wav"]
+        {
+            assert!(error(&format!("game.ch8 {}", option)).ends_with("need --headless"), "{}", option);
+            assert!(parse_words(&format!("game.ch8 --headless {}", option)).is_ok(), "{}", option);
+        }
+        let headless = run_options("game.ch8 --headless").headless.unwrap();
+        assert_eq!((headless.frames, headless.output, headless.format), (None, PathBuf::from("screen.png"), ImageFormat::Png));
+        assert!(error("game.ch8 --headless --frames 1 --instructions 1").contains("cannot be used together"));
+        assert!(error("game.ch8 --headless --output screen.bmp").ends_with("should end with .png or .pbm"));
+    }
+
+    #[test]
+    fn movies_exclude_each_other_and_save_states()
+    {
+        assert_eq!(error("game.ch8 --record a.txt --play b.txt"), "--record and --play cannot be used together");
+        assert_eq!(error("game.ch8 --record a.txt --load-slot 1"), "--load-slot cannot be used with --record or --play");
+        assert_eq!(error("game.ch8 --headless --play a.txt --load-slot 1"), "--load-slot cannot be used with --record or --play");
+        assert_eq!(error("game.ch8 --headless --record a.txt"), "--record needs a window to take the input from");
+        assert_eq!(run_options("game.ch8 --load-slot 9").load_slot, Some(9));
+        assert!(error("game.ch8 --load-slot 10").starts_with("--load-slot goes from 0 to 9"));
+    }
+
+    #[test]
+    fn port_is_only_for_gdb()
+    {
+        match parse_words("gdb game.ch8 --port 2000")
+        {
+            Ok(Command::Gdb { port, .. }) => assert_eq!(port, 2000),
+            result => panic!("{:?}", result),
+        }
+        match parse_words("gdb game.ch8")
+        {
+            Ok(Command::Gdb { port, .. }) => assert_eq!(port, gdb_stub::DEFAULT_PORT),
+            result => panic!("{:?}", result),
+        }
+        assert!(error("gdb game.ch8 --port 0").starts_with("--port goes from 1 to 65535"));
+        assert!(error("gdb game.ch8 --port 65536").starts_with("--port goes from 1 to 65535"));
+        for command in &["game.ch8", "run game.ch8", "debug game.ch8"]
+        {
+            assert!(error(&format!("{} --port 2000", command)).starts_with("unknown option --port"), "{}", command);
+        }
+    }
+
+    #[test]
+    fn subcommands()
+    {
+        assert_eq!(error(""), "no rom given");
+        assert_eq!(error("run"), "no rom given");
+        assert_eq!(error("game.ch8 other.ch8"), "unexpected argument 'other.ch8', only one rom can be given");
+        assert_eq!(run_options("game.ch8").rom, PathBuf::from("game.ch8"));
+        assert_eq!(run_options("run game.ch8").rom, PathBuf::from("game.ch8"));
+        match parse_words("info game.ch8")
+        {
+            Ok(Command::Info(path)) => assert_eq!(path, PathBuf::from("game.ch8")),
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(error("info"), "info expects the path of a rom");
+        assert_eq!(error("info a.ch8 b.ch8"), "info expects only the path of a rom, not 'b.ch8'");
+        match parse_words("disasm game.ch8")
+        {
+            Ok(Command::Disassemble(path)) => assert_eq!(path, PathBuf::from("game.ch8")),
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(error("disasm"), "disasm expects the path of a rom");
+        match parse_words("asm game.asm game.ch8")
+        {
+            Ok(Command::Assemble { source, output }) => assert_eq!((source, output), (PathBuf::from("game.asm"), PathBuf::from("game.ch8"))),
+            result => panic!("{:?}", result),
+        }
+        assert!(error("asm game.asm").starts_with("asm expects"));
+        assert!(matches!(parse_words("dap"), Ok(Command::Dap)));
+        assert!(error("dap game.ch8").starts_with("dap does not take arguments"));
+        assert!(matches!(parse_words("help"), Ok(Command::Help)));
+        assert!(error("game.ch8 --fast").starts_with("unknown option --fast"));
+    }
+}
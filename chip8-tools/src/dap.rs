@@ -0,0 +1,726 @@
+/*
+Debug Adapter Protocol server, for editors that can debug through a DAP adapter.
+Messages are read on stdin and written on stdout, each preceded by a Content-Length header.
+See https://microsoft.github.io/debug-adapter-protocol/specification
+
+A ROM has no source, so the adapter serves a listing of it with one line per 16 bits
+word: line 1 is address 0x200, line 2 is 0x202 and so on. Breakpoints are set on the
+lines of that listing, stack frames point into it.
+Launch arguments: "program" is the path of the ROM, "stopOnEntry", "seed" and "quirks", a preset
+name like the --quirks option, are optional.
+*/
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use chip8_core::chip8::Chip8;
+use chip8_core::debugger::{Debugger, StopReason, Breakpoint};
+use chip8_core::error::Chip8Error;
+use chip8_core::instruction::Instruction;
+use chip8_core::quirks::{self, Quirks};
+use chip8_core::random::RandomGenerator;
+use debug_console;
+use json::Json;
+
+const PROGRAM_START : u16 = 0x200;
+const THREAD_ID : i64 = 1;
+const SOURCE_REFERENCE : i64 = 1;
+const REGISTERS_REFERENCE : i64 = 1;
+const TIMERS_REFERENCE : i64 = 2;
+const STACK_REFERENCE : i64 = 3;
+//Instructions run between two looks at the incoming requests
+const RUN_CHUNK : u64 = 10_000;
+
+//What the program runs until, in chunks of RUN_CHUNK instructions
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Run
+{
+    Continue,
+    //Stepping over or out of a subroutine, until the stack is shallower than this depth
+    Return(usize),
+}
+
+//Serves one session on stdin/stdout
+#[allow(dead_code)]
+pub fn run_stdio() -> io::Result<()>
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move ||
+    {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        while let Ok(Some(message)) = read_message(&mut input)
+        {
+            if sender.send(message).is_err()
+            {
+                break;
+            }
+        }
+    });
+    let stdout = io::stdout();
+    let mut server = DapServer::new(stdout.lock());
+    server.run(receiver)
+}
+
+//None at the end of the input, messages that are not valid JSON are skipped
+#[allow(dead_code)]
+pub fn read_message<R : BufRead>(input : &mut R) -> io::Result<Option<Json>>
+{
+    loop
+    {
+        let mut length = None;
+        loop
+        {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0
+            {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if line.is_empty()
+            {
+                break;
+            }
+            let lower = line.to_lowercase();
+            if let Some(value) = lower.strip_prefix("content-length:")
+            {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let length = match length
+        {
+            Some(length) => length,
+            None => continue,
+        };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        if let Ok(message) = Json::parse(&String::from_utf8_lossy(&body))
+        {
+            return Ok(Some(message));
+        }
+    }
+}
+
+pub struct DapServer<W : Write>
+{
+    output : W,
+    sequence : i64,
+    chip8 : Option<Chip8>,
+    debugger : Debugger,
+    rom : Vec<u8>,
+    rom_name : String,
+    stop_on_entry : bool,
+    running : Option<Run>,
+}
+
+impl<W : Write> DapServer<W>
+{
+    pub fn new(output : W) -> DapServer<W>
+    {
+        DapServer
+        {
+            output,
+            sequence : 0,
+            chip8 : None,
+            debugger : Debugger::default(),
+            rom : Vec::new(),
+            rom_name : String::new(),
+            stop_on_entry : false,
+            running : None,
+        }
+    }
+
+    //Until the client disconnects or closes the input
+    pub fn run(&mut self, requests : Receiver<Json>) -> io::Result<()>
+    {
+        loop
+        {
+            let request = if self.running.is_some()
+            {
+                match requests.try_recv()
+                {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            else
+            {
+                match requests.recv()
+                {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request
+            {
+                if !self.handle_request(&request)?
+                {
+                    return Ok(());
+                }
+            }
+            if let Some(run) = self.running
+            {
+                self.run_chunk(run)?;
+            }
+        }
+    }
+
+    //Returns false when the session is over
+    fn handle_request(&mut self, request : &Json) -> io::Result<bool>
+    {
+        if request.get("type").as_str() != Some("request")
+        {
+            return Ok(true);
+        }
+        let command = request.get("command").as_str().unwrap_or("").to_string();
+        let arguments = request.get("arguments");
+
+        if self.chip8.is_none() && !["initialize", "launch", "disconnect", "terminate", "setExceptionBreakpoints"].contains(&&*command)
+        {
+            self.respond_error(request, "no program is running")?;
+            return Ok(true);
+        }
+
+        match &*command
+        {
+            "initialize" =>
+            {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", Json::from(true)),
+                    ("supportsConditionalBreakpoints", Json::from(true)),
+                    ("supportsReadMemoryRequest", Json::from(true)),
+                    ("supportsTerminateRequest", Json::from(true)),
+                ]);
+                self.respond(request, capabilities)?;
+                self.send_event("initialized", Json::Null)?;
+            }
+            "launch" =>
+            {
+                match self.launch(arguments)
+                {
+                    Ok(()) => self.respond(request, Json::Null)?,
+                    Err(message) => self.respond_error(request, &message)?,
+                }
+            }
+            "setBreakpoints" =>
+            {
+                let body = self.set_breakpoints(arguments);
+                self.respond(request, body)?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, Json::object(vec![("breakpoints", Json::Array(Vec::new()))]))?,
+            "configurationDone" =>
+            {
+                self.respond(request, Json::Null)?;
+                if self.stop_on_entry
+                {
+                    self.send_stopped("entry", None)?;
+                }
+                else
+                {
+                    self.running = Some(Run::Continue);
+                }
+            }
+            "threads" =>
+            {
+                let thread = Json::object(vec![("id", Json::from(THREAD_ID)), ("name", Json::from("CHIP-8"))]);
+                self.respond(request, Json::object(vec![("threads", Json::Array(vec![thread]))]))?;
+            }
+            "stackTrace" =>
+            {
+                let frames = self.stack_frames();
+                let count = frames.len() as i64;
+                self.respond(request, Json::object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", Json::from(count))]))?;
+            }
+            "scopes" =>
+            {
+                let scope = |name : &str, reference : i64| Json::object(vec![
+                    ("name", Json::from(name)),
+                    ("variablesReference", Json::from(reference)),
+                    ("expensive", Json::from(false)),
+                ]);
+                let scopes = vec![scope("Registers", REGISTERS_REFERENCE), scope("Timers", TIMERS_REFERENCE), scope("Stack", STACK_REFERENCE)];
+                self.respond(request, Json::object(vec![("scopes", Json::Array(scopes))]))?;
+            }
+            "variables" =>
+            {
+                let variables = self.variables(arguments.get("variablesReference").as_i64().unwrap_or(0));
+                self.respond(request, Json::object(vec![("variables", Json::Array(variables))]))?;
+            }
+            "source" =>
+            {
+                let content = self.listing();
+                self.respond(request, Json::object(vec![("content", Json::from(content))]))?;
+            }
+            "readMemory" =>
+            {
+                match self.read_memory(arguments)
+                {
+                    Ok(body) => self.respond(request, body)?,
+                    Err(message) => self.respond_error(request, &message)?,
+                }
+            }
+            "continue" =>
+            {
+                self.respond(request, Json::object(vec![("allThreadsContinued", Json::from(true))]))?;
+                self.running = Some(Run::Continue);
+            }
+            "stepOut" =>
+            {
+                let depth = self.chip8.as_ref().unwrap().stack().len();
+                if depth == 0
+                {
+                    self.respond_error(request, "there is no subroutine to step out of")?;
+                }
+                else
+                {
+                    self.respond(request, Json::Null)?;
+                    self.running = Some(Run::Return(depth));
+                }
+            }
+            "next" | "stepIn" =>
+            {
+                self.respond(request, Json::Null)?;
+                self.running = None;
+                let chip8 = self.chip8.as_mut().unwrap();
+                //A call is stepped into here and run to its return in chunks, it may never return
+                let is_call = command == "next" && matches!(chip8.current_instruction(), Some(Instruction::Call(_)));
+                let depth = chip8.stack().len() + 1;
+                match self.debugger.step(chip8)
+                {
+                    Ok(StopReason::Done) if is_call => self.running = Some(Run::Return(depth)),
+                    //Landing on a breakpoint is still the end of a step
+                    Ok(StopReason::Breakpoint(_)) => self.report_stop(Ok(StopReason::Done))?,
+                    result => self.report_stop(result)?,
+                }
+            }
+            "pause" =>
+            {
+                self.respond(request, Json::Null)?;
+                if self.running.is_some()
+                {
+                    self.running = None;
+                    self.send_stopped("pause", None)?;
+                }
+            }
+            "disconnect" | "terminate" =>
+            {
+                self.respond(request, Json::Null)?;
+                if command == "terminate"
+                {
+                    self.send_event("terminated", Json::Null)?;
+                }
+                return Ok(false);
+            }
+            _ => self.respond_error(request, &format!("{} is not supported", command))?,
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments : &Json) -> Result<(), String>
+    {
+        let program = arguments.get("program").as_str().ok_or("the launch configuration needs a program".to_string())?;
+        let quirks = match arguments.get("quirks").as_str()
+        {
+            Some(name) => Quirks::from_name(name).ok_or(format!("unknown quirk preset '{}', the presets are {}", name, quirks::PRESET_NAMES.join(", ")))?,
+            None => Quirks::cosmac_vip(),
+        };
+        let mut rom = Vec::new();
+        File::open(program).and_then(|mut file| file.read_to_end(&mut rom)).map_err(|error| format!("cannot read {}: {}", program, error))?;
+        let seed = arguments.get("seed").as_i64().unwrap_or(0) as u64;
+        let chip8 = Chip8::new(&rom, quirks, RandomGenerator::seeded(seed)).map_err(|error| error.to_string())?;
+
+        self.rom_name = Path::new(program).file_name().map_or("rom".to_string(), |name| name.to_string_lossy().into_owned());
+        self.rom = rom;
+        self.chip8 = Some(chip8);
+        self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+        Ok(())
+    }
+
+    //Replaces every breakpoint, there is only one source
+    fn set_breakpoints(&mut self, arguments : &Json) -> Json
+    {
+        self.debugger.clear_breakpoints();
+        let mut results = Vec::new();
+        for requested in arguments.get("breakpoints").as_array().unwrap_or(&[])
+        {
+            let line = requested.get("line").as_i64().unwrap_or(0);
+            let condition = match requested.get("condition").as_str()
+            {
+                Some(condition) if !condition.trim().is_empty() =>
+                {
+                    let words : Vec<&str> = condition.split_whitespace().collect();
+                    match debug_console::parse_condition(&words)
+                    {
+                        Ok(condition) => Some(condition),
+                        Err(message) =>
+                        {
+                            results.push(Json::object(vec![("verified", Json::from(false)), ("line", Json::from(line)), ("message", Json::from(message))]));
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let address = match address_of_line(line)
+            {
+                Some(address) => address,
+                None =>
+                {
+                    results.push(Json::object(vec![("verified", Json::from(false)), ("line", Json::from(line)), ("message", Json::from("no instruction on this line"))]));
+                    continue;
+                }
+            };
+            let id = self.debugger.add_breakpoint(Breakpoint { address : Some(address), condition });
+            results.push(Json::object(vec![
+                ("id", Json::from(id as i64)),
+                ("verified", Json::from(true)),
+                ("line", Json::from(line)),
+                ("source", self.source()),
+            ]));
+        }
+        Json::object(vec![("breakpoints", Json::Array(results))])
+    }
+
+    //The current instruction first, then the return address of each call
+    fn stack_frames(&self) -> Vec<Json>
+    {
+        let chip8 = self.chip8.as_ref().unwrap();
+        let mut addresses = vec![chip8.program_counter()];
+        addresses.extend(chip8.stack().iter().rev());
+
+        addresses.iter().enumerate().map(|(id, address)|
+        {
+            let instruction = match read_instruction(chip8.memory(), *address)
+            {
+                Some(instruction) => instruction.to_string(),
+                None => "outside of memory".to_string(),
+            };
+            let mut members = vec![
+                ("id", Json::from(id as i64)),
+                ("name", Json::from(format!("{:03X}: {}", address, instruction))),
+                ("line", Json::from(line_of_address(*address))),
+                ("column", Json::from(1)),
+                ("instructionPointerReference", Json::from(format!("{:#X}", address))),
+            ];
+            if *address >= PROGRAM_START
+            {
+                members.push(("source", self.source()));
+            }
+            Json::object(members)
+        }).collect()
+    }
+
+    fn variables(&self, reference : i64) -> Vec<Json>
+    {
+        let chip8 = self.chip8.as_ref().unwrap();
+        let variable = |name : String, value : String, memory_reference : Option<u16>|
+        {
+            let mut members = vec![("name", Json::from(name)), ("value", Json::from(value)), ("variablesReference", Json::from(0))];
+            if let Some(address) = memory_reference
+            {
+                members.push(("memoryReference", Json::from(format!("{:#X}", address))));
+            }
+            Json::object(members)
+        };
+        match reference
+        {
+            REGISTERS_REFERENCE =>
+            {
+                let mut variables : Vec<Json> = chip8.registers().iter().enumerate()
+                    .map(|(x, value)| variable(format!("V{:X}", x), format!("{:#04X} ({})", value, value), None))
+                    .collect();
+                variables.push(variable("I".to_string(), format!("{:#05X}", chip8.address_register()), Some(chip8.address_register())));
+                variables.push(variable("PC".to_string(), format!("{:#05X}", chip8.program_counter()), Some(chip8.program_counter())));
+                variables.push(variable("SP".to_string(), chip8.stack().len().to_string(), None));
+                variables
+            }
+            TIMERS_REFERENCE => vec![
+                variable("DT".to_string(), chip8.delay_timer().to_string(), None),
+                variable("ST".to_string(), chip8.sound_timer().to_string(), None),
+            ],
+            STACK_REFERENCE => chip8.stack().iter().enumerate().rev()
+                .map(|(depth, address)| variable(format!("[{}]", depth), format!("{:#05X}", address), Some(*address)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_memory(&self, arguments : &Json) -> Result<Json, String>
+    {
+        let memory = self.chip8.as_ref().unwrap().memory();
+        let reference = arguments.get("memoryReference").as_str().ok_or("missing memoryReference".to_string())?;
+        let base = parse_reference(reference).ok_or(format!("'{}' is not an address", reference))?;
+        let address = base + arguments.get("offset").as_i64().unwrap_or(0);
+        let count = arguments.get("count").as_i64().unwrap_or(0).max(0);
+
+        let start = address.max(0).min(memory.len() as i64);
+        let end = (address + count).max(0).min(memory.len() as i64);
+        let readable = if end > start { &memory[start as usize .. end as usize] } else { &[][..] };
+        Ok(Json::object(vec![
+            ("address", Json::from(format!("{:#X}", address))),
+            ("data", Json::from(encode_base64(readable))),
+            ("unreadableBytes", Json::from(count - readable.len() as i64)),
+        ]))
+    }
+
+    fn run_chunk(&mut self, run : Run) -> io::Result<()>
+    {
+        let chip8 = self.chip8.as_mut().unwrap();
+        let result = match run
+        {
+            Run::Continue => self.debugger.resume(chip8, Some(RUN_CHUNK)),
+            Run::Return(depth) => self.debugger.run_to_return(chip8, depth, Some(RUN_CHUNK)),
+        };
+        if result != Ok(StopReason::Limit)
+        {
+            self.running = None;
+            self.report_stop(result)?;
+        }
+        Ok(())
+    }
+
+    fn report_stop(&mut self, result : Result<StopReason, Chip8Error>) -> io::Result<()>
+    {
+        match result
+        {
+            Ok(StopReason::Done) | Ok(StopReason::Limit) => self.send_stopped("step", None),
+            Ok(StopReason::Breakpoint(index)) => self.send_stopped("breakpoint", Some(index)),
+            Ok(StopReason::Watchpoint { .. }) => self.send_stopped("data breakpoint", None),
+            Ok(StopReason::Halted) =>
+            {
+                self.send_event("exited", Json::object(vec![("exitCode", Json::from(0))]))?;
+                self.send_event("terminated", Json::Null)
+            }
+            Err(error) =>
+            {
+                self.send_event("output", Json::object(vec![("category", Json::from("stderr")), ("output", Json::from(format!("{}\n", error)))]))?;
+                let body = Json::object(vec![
+                    ("reason", Json::from("exception")),
+                    ("description", Json::from(error.to_string())),
+                    ("threadId", Json::from(THREAD_ID)),
+                    ("allThreadsStopped", Json::from(true)),
+                ]);
+                self.send_event("stopped", body)
+            }
+        }
+    }
+
+    fn send_stopped(&mut self, reason : &str, breakpoint : Option<usize>) -> io::Result<()>
+    {
+        let mut members = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(index) = breakpoint
+        {
+            members.push(("hitBreakpointIds", Json::Array(vec![Json::from(index as i64)])));
+        }
+        self.send_event("stopped", Json::object(members))
+    }
+
+    fn source(&self) -> Json
+    {
+        Json::object(vec![("name", Json::from(format!("{}.lst", self.rom_name))), ("sourceReference", Json::from(SOURCE_REFERENCE))])
+    }
+
+    //One line per word of the rom
+    fn listing(&self) -> String
+    {
+        let mut listing = String::new();
+        for offset in (0 .. self.rom.len()).step_by(2)
+        {
+            let address = PROGRAM_START as usize + offset;
+            let opcode = (self.rom[offset] as u16) << 8 | *self.rom.get(offset + 1).unwrap_or(&0) as u16;
+            listing.push_str(&format!("{:03X}: {:04X}  {}\n", address, opcode, Instruction::decode(opcode)));
+        }
+        listing
+    }
+
+    fn respond(&mut self, request : &Json, body : Json) -> io::Result<()>
+    {
+        let mut members = vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("success", Json::from(true)),
+            ("command", request.get("command").clone()),
+        ];
+        if body != Json::Null
+        {
+            members.push(("body", body));
+        }
+        self.send(members)
+    }
+
+    fn respond_error(&mut self, request : &Json, message : &str) -> io::Result<()>
+    {
+        self.send(vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("success", Json::from(false)),
+            ("command", request.get("command").clone()),
+            ("message", Json::from(message)),
+        ])
+    }
+
+    fn send_event(&mut self, event : &str, body : Json) -> io::Result<()>
+    {
+        let mut members = vec![("type", Json::from("event")), ("event", Json::from(event))];
+        if body != Json::Null
+        {
+            members.push(("body", body));
+        }
+        self.send(members)
+    }
+
+    fn send(&mut self, members : Vec<(&str, Json)>) -> io::Result<()>
+    {
+        self.sequence += 1;
+        let mut message = vec![("seq", Json::from(self.sequence))];
+        message.extend(members);
+        let body = Json::object(message).to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+fn line_of_address(address : u16) -> i64
+{
+    if address < PROGRAM_START { 0 } else { ((address - PROGRAM_START) / 2) as i64 + 1 }
+}
+
+fn address_of_line(line : i64) -> Option<u16>
+{
+    let address = PROGRAM_START as i64 + (line - 1) * 2;
+    if line >= 1 && address <= 0xFFFE { Some(address as u16) } else { None }
+}
+
+fn read_instruction(memory : &[u8], address : u16) -> Option<Instruction>
+{
+    let address = address as usize;
+    if address + 1 < memory.len()
+    {
+        Some(Instruction::decode((memory[address] as u16) << 8 | memory[address + 1] as u16))
+    }
+    else
+    {
+        None
+    }
+}
+
+//Memory references are the addresses given in variables, 0x prefixed or decimal
+fn parse_reference(reference : &str) -> Option<i64>
+{
+    if reference.starts_with("0x") || reference.starts_with("0X")
+    {
+        i64::from_str_radix(&reference[2 ..], 16).ok()
+    }
+    else
+    {
+        reference.parse::<i64>().ok()
+    }
+}
+
+fn encode_base64(data : &[u8]) -> String
+{
+    const ALPHABET : &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3)
+    {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0 .. 4
+        {
+            if i <= chunk.len()
+            {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            }
+            else
+            {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    //Calls a subroutine that never returns
+    const ROM : [u8; 6] = [0x22, 0x04, 0x00, 0xE0, 0x12, 0x04];
+
+    fn request(command : &str, arguments : Json) -> Json
+    {
+        Json::object(vec![("seq", Json::from(1)), ("type", Json::from("request")), ("command", Json::from(command)), ("arguments", arguments)])
+    }
+
+    //Runs a whole session on the given requests and returns the messages sent back
+    fn session(name : &str, requests : Vec<Json>) -> Vec<Json>
+    {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, ROM).unwrap();
+        let launch = Json::object(vec![
+            ("program", Json::from(&*path.to_string_lossy())),
+            ("stopOnEntry", Json::from(true)),
+            ("quirks", Json::from("schip")),
+        ]);
+        let (sender, receiver) = mpsc::channel();
+        sender.send(request("launch", launch)).unwrap();
+        sender.send(request("configurationDone", Json::Null)).unwrap();
+        for request in requests
+        {
+            sender.send(request).unwrap();
+        }
+        sender.send(request("disconnect", Json::Null)).unwrap();
+
+        let mut server = DapServer::new(Vec::new());
+        server.run(receiver).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut output = &server.output[..];
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap()
+        {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn stop_reasons(messages : &[Json]) -> Vec<&str>
+    {
+        messages.iter().filter(|message| message.get("event").as_str() == Some("stopped")).filter_map(|message| message.get("body").get("reason").as_str()).collect()
+    }
+
+    #[test]
+    fn next_over_a_call_that_never_returns_can_be_paused()
+    {
+        let messages = session("dap_next.ch8", vec![request("next", Json::Null), request("pause", Json::Null)]);
+        assert_eq!(stop_reasons(&messages), ["entry", "pause"]);
+    }
+
+    #[test]
+    fn step_out_needs_a_subroutine()
+    {
+        let messages = session("dap_step_out.ch8", vec![request("stepOut", Json::Null)]);
+        let response = messages.iter().find(|message| message.get("command").as_str() == Some("stepOut")).unwrap();
+        assert_eq!(response.get("success").as_bool(), Some(false));
+    }
+
+    #[test]
+    fn unknown_quirk_presets_are_rejected()
+    {
+        let launch = Json::object(vec![("program", Json::from("missing.ch8")), ("quirks", Json::from("cosmac"))]);
+        let mut server = DapServer::new(Vec::new());
+        assert!(server.launch(&launch).unwrap_err().starts_with("unknown quirk preset"));
+    }
+}
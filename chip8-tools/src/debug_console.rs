@@ -0,0 +1,425 @@
+/*
+Command line front end of the debugger, started with the debug mode:
+
+    step [n]                    s       execute n instructions
+    next                        n       step over subroutine calls
+    finish                      f       run until the current subroutine returns
+    continue [n]                c       run until a breakpoint or watchpoint, at most n instructions
+    frame <n>                           run until frame n starts
+    break <address> [if <cond>] b       add a breakpoint, the condition looks like "V3 == 0x10"
+    break if <cond>                     stop as soon as the condition is true
+    watch <address> [length] [r|w|rw]   w  stop after memory is read and/or written
+    delete <n>                          remove breakpoint n
+    unwatch <n>                         remove watchpoint n
+    list                                show breakpoints and watchpoints
+    registers                   r       show registers, I, timers and stack
+    disassemble [address] [count] d     disassemble around the program counter or from an address
+    memory <address> [length]   x       hex dump
+    press <key> / release <key>         hold or release a key of the keypad
+    quit                        q
+
+An empty line repeats the previous command. Conditions compare V0-VF, I, DT or ST with
+==, !=, <, <=, > or >=.
+*/
+
+use std::io;
+use std::io::prelude::*;
+use chip8_core::chip8::{Chip8, MemoryAccessKind};
+use chip8_core::debugger::{Debugger, DEFAULT_RUN_LIMIT, StopReason, Breakpoint, Condition, Register, Comparison, Watchpoint, WatchKind};
+use chip8_core::error::Chip8Error;
+use chip8_core::instruction::Instruction;
+
+const DISASSEMBLY_BEFORE : u16 = 4;
+const DISASSEMBLY_LENGTH : u16 = 10;
+
+#[allow(dead_code)]
+pub fn run<R : BufRead, W : Write>(chip8 : &mut Chip8, debugger : &mut Debugger, input : R, output : &mut W) -> io::Result<()>
+{
+    print_location(chip8, debugger, output)?;
+    write!(output, "> ")?;
+    output.flush()?;
+
+    let mut previous_command = String::new();
+    for line in input.lines()
+    {
+        let line = line?;
+        let command = if line.trim().is_empty() { previous_command.clone() } else { line.trim().to_string() };
+        previous_command = command.clone();
+
+        let words : Vec<&str> = command.split_whitespace().collect();
+        if words.first() == Some(&"quit") || words.first() == Some(&"q")
+        {
+            return Ok(());
+        }
+        if !words.is_empty()
+        {
+            if let Err(message) = execute_command(chip8, debugger, &words, output)?
+            {
+                writeln!(output, "{}", message)?;
+            }
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+//The outer error is for the output, the inner one for the user
+fn execute_command<W : Write>(chip8 : &mut Chip8, debugger : &mut Debugger, words : &[&str], output : &mut W) -> io::Result<Result<(), String>>
+{
+    let arguments = &words[1 ..];
+    match words[0]
+    {
+        "step" | "s" =>
+        {
+            let count = match optional_number(arguments, 0, 1)
+            {
+                Ok(count) => count,
+                Err(message) => return Ok(Err(message)),
+            };
+            let mut result = Ok(StopReason::Done);
+            for _ in 0 .. count
+            {
+                result = debugger.step(chip8);
+                if result != Ok(StopReason::Done)
+                {
+                    break;
+                }
+            }
+            report_stop(chip8, debugger, result, output)?;
+        }
+        "next" | "n" =>
+        {
+            let result = debugger.step_over(chip8, Some(DEFAULT_RUN_LIMIT));
+            report_stop(chip8, debugger, result, output)?;
+        }
+        "finish" | "f" =>
+        {
+            if chip8.stack().is_empty()
+            {
+                return Ok(Err("not inside a subroutine".to_string()));
+            }
+            let result = debugger.step_out(chip8, Some(DEFAULT_RUN_LIMIT));
+            report_stop(chip8, debugger, result, output)?;
+        }
+        "continue" | "c" =>
+        {
+            let limit = match optional_number(arguments, 0, DEFAULT_RUN_LIMIT as usize)
+            {
+                Ok(limit) => limit,
+                Err(message) => return Ok(Err(message)),
+            };
+            let result = debugger.resume(chip8, Some(limit as u64));
+            report_stop(chip8, debugger, result, output)?;
+        }
+        "frame" =>
+        {
+            let frame = match arguments.first().and_then(|word| parse_number(word))
+            {
+                Some(frame) => frame,
+                None => return Ok(Err("usage: frame <n>".to_string())),
+            };
+            let result = debugger.run_to_frame(chip8, frame as u64);
+            report_stop(chip8, debugger, result, output)?;
+        }
+        "break" | "b" =>
+        {
+            let breakpoint = match parse_breakpoint(arguments)
+            {
+                Ok(breakpoint) => breakpoint,
+                Err(message) => return Ok(Err(message)),
+            };
+            let index = debugger.add_breakpoint(breakpoint);
+            writeln!(output, "Breakpoint {}: {}", index, breakpoint)?;
+        }
+        "watch" | "w" =>
+        {
+            let watchpoint = match parse_watchpoint(arguments)
+            {
+                Ok(watchpoint) => watchpoint,
+                Err(message) => return Ok(Err(message)),
+            };
+            let index = debugger.add_watchpoint(watchpoint);
+            writeln!(output, "Watchpoint {}: {}", index, watchpoint)?;
+        }
+        "delete" =>
+        {
+            match arguments.first().and_then(|word| parse_number(word)).and_then(|index| debugger.remove_breakpoint(index))
+            {
+                Some(breakpoint) => writeln!(output, "Deleted breakpoint {}", breakpoint)?,
+                None => return Ok(Err("usage: delete <breakpoint number>".to_string())),
+            }
+        }
+        "unwatch" =>
+        {
+            match arguments.first().and_then(|word| parse_number(word)).and_then(|index| debugger.remove_watchpoint(index))
+            {
+                Some(watchpoint) => writeln!(output, "Deleted watchpoint {}", watchpoint)?,
+                None => return Ok(Err("usage: unwatch <watchpoint number>".to_string())),
+            }
+        }
+        "list" =>
+        {
+            for (index, breakpoint) in debugger.breakpoints().iter().enumerate()
+            {
+                writeln!(output, "Breakpoint {}: {}", index, breakpoint)?;
+            }
+            for (index, watchpoint) in debugger.watchpoints().iter().enumerate()
+            {
+                writeln!(output, "Watchpoint {}: {}", index, watchpoint)?;
+            }
+        }
+        "registers" | "r" => print_registers(chip8, debugger, output)?,
+        "disassemble" | "d" =>
+        {
+            let start = match arguments.first()
+            {
+                Some(word) => match parse_number(word)
+                {
+                    Some(address) => address as u16,
+                    None => return Ok(Err(format!("'{}' is not an address", word))),
+                },
+                None => chip8.program_counter().saturating_sub(DISASSEMBLY_BEFORE * 2),
+            };
+            let count = match optional_number(arguments, 1, DISASSEMBLY_LENGTH as usize)
+            {
+                Ok(count) => count,
+                Err(message) => return Ok(Err(message)),
+            };
+            print_disassembly(chip8, start, count, output)?;
+        }
+        "memory" | "x" =>
+        {
+            let address = match arguments.first().and_then(|word| parse_number(word))
+            {
+                Some(address) => address,
+                None => return Ok(Err("usage: memory <address> [length]".to_string())),
+            };
+            let length = match optional_number(arguments, 1, 64)
+            {
+                Ok(0) => return Ok(Err("the length must be at least 1".to_string())),
+                Ok(length) => length,
+                Err(message) => return Ok(Err(message)),
+            };
+            if address.checked_add(length).is_none()
+            {
+                return Ok(Err(format!("'{}' is not an address", arguments[0])));
+            }
+            print_memory(chip8, address, length, output)?;
+        }
+        "press" | "release" =>
+        {
+            let key = match arguments.first().and_then(|word| u8::from_str_radix(word, 16).ok())
+            {
+                Some(key) if key < 16 => key,
+                _ => return Ok(Err(format!("usage: {} <key 0-F>", words[0]))),
+            };
+            if words[0] == "press" { chip8.press_key(key) } else { chip8.release_key(key) }
+        }
+        "help" | "h" =>
+        {
+            writeln!(output, "step [n], next, finish, continue [n], frame <n>, break <address> [if <condition>], break if <condition>,")?;
+            writeln!(output, "watch <address> [length] [r|w|rw], delete <n>, unwatch <n>, list, registers, disassemble [address] [count],")?;
+            writeln!(output, "memory <address> [length], press <key>, release <key>, quit")?;
+        }
+        command => return Ok(Err(format!("unknown command '{}', type help for the list", command))),
+    }
+    Ok(Ok(()))
+}
+
+fn report_stop<W : Write>(chip8 : &Chip8, debugger : &Debugger, result : Result<StopReason, Chip8Error>, output : &mut W) -> io::Result<()>
+{
+    match result
+    {
+        Ok(StopReason::Done) => (),
+        Ok(StopReason::Breakpoint(index)) => writeln!(output, "Breakpoint {}: {}", index, debugger.breakpoints()[index])?,
+        Ok(StopReason::Watchpoint { index, kind, address }) =>
+        {
+            let access = if kind == MemoryAccessKind::Read { "read" } else { "write" };
+            writeln!(output, "Watchpoint {}: {} of {:#05X}", index, access, address)?;
+        }
+        Ok(StopReason::Halted) => writeln!(output, "The program has halted")?,
+        Ok(StopReason::Limit) => writeln!(output, "Stopped after the instruction limit")?,
+        Err(error) => writeln!(output, "Error: {}", error)?,
+    }
+    print_location(chip8, debugger, output)
+}
+
+fn print_location<W : Write>(chip8 : &Chip8, debugger : &Debugger, output : &mut W) -> io::Result<()>
+{
+    let instruction = match chip8.current_instruction()
+    {
+        Some(instruction) => instruction.to_string(),
+        None => "outside of memory".to_string(),
+    };
+    writeln!(output, "frame {} {:03X}: {}", debugger.frame(), chip8.program_counter(), instruction)
+}
+
+fn print_registers<W : Write>(chip8 : &Chip8, debugger : &Debugger, output : &mut W) -> io::Result<()>
+{
+    for row in 0 .. 2
+    {
+        for x in row * 8 .. row * 8 + 8
+        {
+            write!(output, "V{:X}={:02X} ", x, chip8.registers()[x])?;
+        }
+        writeln!(output)?;
+    }
+    writeln!(output, "PC={:03X} I={:03X} DT={:02X} ST={:02X} frame={} instructions={}",
+        chip8.program_counter(), chip8.address_register(), chip8.delay_timer(), chip8.sound_timer(), debugger.frame(), chip8.instruction_count())?;
+    write!(output, "stack:")?;
+    for address in chip8.stack().iter().rev()
+    {
+        write!(output, " {:03X}", address)?;
+    }
+    writeln!(output)
+}
+
+fn print_disassembly<W : Write>(chip8 : &Chip8, start : u16, count : usize, output : &mut W) -> io::Result<()>
+{
+    let memory = chip8.memory();
+    let mut address = start as usize;
+    for _ in 0 .. count
+    {
+        if address + 1 >= memory.len()
+        {
+            break;
+        }
+        let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+        let instruction = Instruction::decode(opcode);
+        let marker = if address == chip8.program_counter() as usize { ">" } else { " " };
+        writeln!(output, "{} {:03X}: {:04X}  {}", marker, address, opcode, instruction)?;
+        address += instruction.size() as usize;
+    }
+    Ok(())
+}
+
+fn print_memory<W : Write>(chip8 : &Chip8, address : usize, length : usize, output : &mut W) -> io::Result<()>
+{
+    let memory = chip8.memory();
+    let end = (address + length).min(memory.len());
+    let mut row = address;
+    while row < end
+    {
+        write!(output, "{:03X}:", row)?;
+        for byte in &memory[row .. (row + 16).min(end)]
+        {
+            write!(output, " {:02X}", byte)?;
+        }
+        writeln!(output)?;
+        row += 16;
+    }
+    Ok(())
+}
+
+fn parse_breakpoint(arguments : &[&str]) -> Result<Breakpoint, String>
+{
+    let usage = "usage: break <address> [if <condition>] or break if <condition>".to_string();
+    match arguments
+    {
+        [] => Err(usage),
+        ["if", condition @ ..] => Ok(Breakpoint { address : None, condition : Some(parse_condition(condition)?) }),
+        [address] => Ok(Breakpoint { address : Some(parse_address(address)?), condition : None }),
+        [address, "if", condition @ ..] => Ok(Breakpoint { address : Some(parse_address(address)?), condition : Some(parse_condition(condition)?) }),
+        _ => Err(usage),
+    }
+}
+
+//Also used for the conditional breakpoints of the debug adapter
+pub fn parse_condition(words : &[&str]) -> Result<Condition, String>
+{
+    if words.len() != 3
+    {
+        return Err("a condition looks like V3 == 0x10".to_string());
+    }
+    let register = match &*words[0].to_uppercase()
+    {
+        "I" => Register::I,
+        "DT" => Register::DelayTimer,
+        "ST" => Register::SoundTimer,
+        name if name.len() == 2 && name.starts_with('V') => match u8::from_str_radix(&name[1 ..], 16)
+        {
+            Ok(x) => Register::V(x),
+            Err(_) => return Err(format!("'{}' is not a register", words[0])),
+        },
+        _ => return Err(format!("'{}' is not a register", words[0])),
+    };
+    let comparison = match words[1]
+    {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        _ => return Err(format!("'{}' is not a comparison", words[1])),
+    };
+    let value = match parse_number(words[2])
+    {
+        Some(value) if value <= 0xFFFF => value as u16,
+        _ => return Err(format!("'{}' is not a value", words[2])),
+    };
+    Ok(Condition { register, comparison, value })
+}
+
+fn parse_watchpoint(arguments : &[&str]) -> Result<Watchpoint, String>
+{
+    let address = match arguments.first()
+    {
+        Some(word) => parse_address(word)?,
+        None => return Err("usage: watch <address> [length] [r|w|rw]".to_string()),
+    };
+    let mut length = 1;
+    let mut kind = WatchKind::Write;
+    for word in &arguments[1 ..]
+    {
+        match *word
+        {
+            "r" => kind = WatchKind::Read,
+            "w" => kind = WatchKind::Write,
+            "rw" => kind = WatchKind::ReadWrite,
+            _ => match parse_number(word)
+            {
+                Some(value) if value > 0 && value <= 0xFFFF => length = value as u16,
+                _ => return Err(format!("'{}' is not a length", word)),
+            },
+        }
+    }
+    Ok(Watchpoint { address, length, kind })
+}
+
+fn parse_address(word : &str) -> Result<u16, String>
+{
+    match parse_number(word)
+    {
+        Some(address) if address <= 0xFFFF => Ok(address as u16),
+        _ => Err(format!("'{}' is not an address", word)),
+    }
+}
+
+fn optional_number(arguments : &[&str], index : usize, default : usize) -> Result<usize, String>
+{
+    match arguments.get(index)
+    {
+        Some(word) => parse_number(word).ok_or(format!("'{}' is not a number", word)),
+        None => Ok(default),
+    }
+}
+
+//Addresses are usually typed in hexadecimal, so 0x, # and $ are all accepted
+fn parse_number(word : &str) -> Option<usize>
+{
+    let lower = word.to_lowercase();
+    if let Some(digits) = lower.strip_prefix("0x")
+    {
+        usize::from_str_radix(digits, 16).ok()
+    }
+    else if let Some(digits) = lower.strip_prefix('#').or_else(|| lower.strip_prefix('$'))
+    {
+        usize::from_str_radix(digits, 16).ok()
+    }
+    else
+    {
+        lower.parse::<usize>().ok()
+    }
+}
@@ -0,0 +1,624 @@
+/*
+GDB remote serial protocol stub. It listens on localhost, serves a single debugger
+connection and runs the interpreter through the debugger engine.
+Supported: register and memory read/write, software breakpoints, write/read/access
+watchpoints, single step, continue with ctrl-c interruption, and the register layout
+in chip8-target.xml through qXfer:features:read.
+The protocol is described at https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+*/
+
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use chip8_core::chip8::{Chip8, MemoryAccessKind};
+use chip8_core::debugger::{Debugger, StopReason, Breakpoint, Watchpoint, WatchKind};
+use chip8_core::error::Chip8Error;
+
+pub const DEFAULT_PORT : u16 = 1234;
+const TARGET_XML : &str = include_str!("chip8-target.xml");
+
+//V0-VF, I, PC, SP, DT, ST
+const REGISTER_COUNT : usize = 21;
+const STACK_DEPTH_REGISTER : usize = 18;
+//Instructions run between two checks for ctrl-c
+const RUN_CHUNK : u64 = 10_000;
+const INTERRUPT : u8 = 0x03;
+
+//The stub only listens on localhost, the caller accepts the connection and hands it to GdbStub::new
+#[allow(dead_code)]
+pub fn listen(port : u16) -> io::Result<TcpListener>
+{
+    TcpListener::bind(("127.0.0.1", port))
+}
+
+enum Packet
+{
+    Command(Vec<u8>),
+    Interrupt,
+}
+
+pub struct GdbStub
+{
+    stream : TcpStream,
+    //bytes received while checking for ctrl-c
+    pending : Vec<u8>,
+    no_ack : bool,
+}
+
+impl GdbStub
+{
+    pub fn new(stream : TcpStream) -> io::Result<GdbStub>
+    {
+        stream.set_nodelay(true)?;
+        Ok(GdbStub { stream, pending : Vec::new(), no_ack : false })
+    }
+
+    pub fn run(&mut self, chip8 : &mut Chip8, debugger : &mut Debugger) -> io::Result<()>
+    {
+        loop
+        {
+            let packet = match self.receive_packet()?
+            {
+                Some(Packet::Command(packet)) => packet,
+                //Already stopped
+                Some(Packet::Interrupt) => continue,
+                None => return Ok(()),
+            };
+            if !self.handle_packet(chip8, debugger, &packet)?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    //Returns false when the session is over
+    fn handle_packet(&mut self, chip8 : &mut Chip8, debugger : &mut Debugger, packet : &[u8]) -> io::Result<bool>
+    {
+        let text = String::from_utf8_lossy(packet).into_owned();
+        let command = match packet.first() { Some(byte) => *byte as char, None => ' ' };
+        let arguments = text.get(1 ..).unwrap_or("");
+        match command
+        {
+            '?' => self.send_packet(b"S05")?,
+            'g' =>
+            {
+                let mut reply = String::new();
+                for index in 0 .. REGISTER_COUNT
+                {
+                    reply.push_str(&read_register(chip8, index));
+                }
+                self.send_packet(reply.as_bytes())?;
+            }
+            'G' =>
+            {
+                let reply = match write_all_registers(chip8, arguments) { Some(()) => "OK", None => "E01" };
+                self.send_packet(reply.as_bytes())?;
+            }
+            'p' =>
+            {
+                let reply = match usize::from_str_radix(arguments, 16)
+                {
+                    Ok(index) if index < REGISTER_COUNT => read_register(chip8, index),
+                    _ => "E01".to_string(),
+                };
+                self.send_packet(reply.as_bytes())?;
+            }
+            'P' =>
+            {
+                let mut parts = arguments.splitn(2, '=');
+                let index = parts.next().and_then(|index| usize::from_str_radix(index, 16).ok());
+                let value = parts.next().and_then(decode_hex);
+                let reply = match (index, value)
+                {
+                    (Some(index), Some(value)) if index < REGISTER_COUNT && write_register(chip8, index, &value).is_some() => "OK",
+                    _ => "E01",
+                };
+                self.send_packet(reply.as_bytes())?;
+            }
+            'm' =>
+            {
+                let reply = match parse_address_length(arguments)
+                {
+                    Some((address, length)) if address < chip8.memory().len() && address.checked_add(length).is_some() =>
+                    {
+                        let end = (address + length).min(chip8.memory().len());
+                        encode_hex(&chip8.memory()[address .. end])
+                    }
+                    _ => "E01".to_string(),
+                };
+                self.send_packet(reply.as_bytes())?;
+            }
+            'M' =>
+            {
+                let mut parts = arguments.splitn(2, ':');
+                let location = parts.next().and_then(parse_address_length);
+                let data = parts.next().and_then(decode_hex);
+                let reply = match (location, data)
+                {
+                    (Some((address, length)), Some(data)) if data.len() == length && chip8.set_memory(address, &data).is_ok() => "OK",
+                    _ => "E01",
+                };
+                self.send_packet(reply.as_bytes())?;
+            }
+            'Z' | 'z' =>
+            {
+                let reply = self.change_breakpoint(debugger, command == 'Z', arguments);
+                self.send_packet(reply.as_bytes())?;
+            }
+            's' =>
+            {
+                if let Some(address) = parse_resume_address(arguments)
+                {
+                    chip8.set_program_counter(address);
+                }
+                let reply = match debugger.step(chip8)
+                {
+                    Ok(StopReason::Breakpoint(_)) => "S05".to_string(),
+                    result => stop_reply(debugger, result),
+                };
+                self.send_packet(reply.as_bytes())?;
+                return Ok(!has_exited(&reply));
+            }
+            'c' =>
+            {
+                if let Some(address) = parse_resume_address(arguments)
+                {
+                    chip8.set_program_counter(address);
+                }
+                let reply = self.resume(chip8, debugger)?;
+                self.send_packet(reply.as_bytes())?;
+                return Ok(!has_exited(&reply));
+            }
+            'D' =>
+            {
+                self.send_packet(b"OK")?;
+                return Ok(false);
+            }
+            'k' => return Ok(false),
+            'H' | 'T' => self.send_packet(b"OK")?,
+            'q' | 'Q' | 'v' => self.handle_query(&text)?,
+            //Everything else is reported as unsupported
+            _ => self.send_packet(b"")?,
+        }
+        Ok(true)
+    }
+
+    fn handle_query(&mut self, query : &str) -> io::Result<()>
+    {
+        if query.starts_with("qSupported")
+        {
+            self.send_packet(b"PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+")
+        }
+        else if query == "QStartNoAckMode"
+        {
+            self.send_packet(b"OK")?;
+            self.no_ack = true;
+            Ok(())
+        }
+        else if let Some(range) = query.strip_prefix("qXfer:features:read:target.xml:")
+        {
+            let reply = match parse_address_length(range)
+            {
+                Some((offset, length)) if offset <= TARGET_XML.len() && offset.checked_add(length).is_some() =>
+                {
+                    let end = (offset + length).min(TARGET_XML.len());
+                    let mut reply = vec![if end == TARGET_XML.len() { b'l' } else { b'm' }];
+                    reply.extend_from_slice(&escape_binary(&TARGET_XML.as_bytes()[offset .. end]));
+                    reply
+                }
+                _ => b"E01".to_vec(),
+            };
+            self.send_packet(&reply)
+        }
+        else if query == "qAttached"
+        {
+            self.send_packet(b"1")
+        }
+        else if query == "qC"
+        {
+            self.send_packet(b"QC1")
+        }
+        else if query == "qfThreadInfo"
+        {
+            self.send_packet(b"m1")
+        }
+        else if query == "qsThreadInfo"
+        {
+            self.send_packet(b"l")
+        }
+        else if query.starts_with("qSymbol")
+        {
+            self.send_packet(b"OK")
+        }
+        else
+        {
+            self.send_packet(b"")
+        }
+    }
+
+    //Z0/Z1 breakpoints, Z2 write, Z3 read and Z4 access watchpoints
+    fn change_breakpoint(&self, debugger : &mut Debugger, insert : bool, arguments : &str) -> String
+    {
+        let parts : Vec<&str> = arguments.split(',').collect();
+        if parts.len() < 3
+        {
+            return "E01".to_string();
+        }
+        let address = match u16::from_str_radix(parts[1], 16) { Ok(address) => address, Err(_) => return "E01".to_string() };
+        let length = match u16::from_str_radix(parts[2].split(';').next().unwrap(), 16) { Ok(length) => length.max(1), Err(_) => return "E01".to_string() };
+
+        let kind = match parts[0]
+        {
+            "0" | "1" =>
+            {
+                let breakpoint = Breakpoint { address : Some(address), condition : None };
+                if insert
+                {
+                    debugger.add_breakpoint(breakpoint);
+                }
+                else if let Some(index) = debugger.breakpoints().iter().position(|other| *other == breakpoint)
+                {
+                    debugger.remove_breakpoint(index);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint { address, length, kind };
+        if insert
+        {
+            debugger.add_watchpoint(watchpoint);
+        }
+        else if let Some(index) = debugger.watchpoints().iter().position(|other| *other == watchpoint)
+        {
+            debugger.remove_watchpoint(index);
+        }
+        "OK".to_string()
+    }
+
+    //Runs until something stops the program or gdb sends ctrl-c
+    fn resume(&mut self, chip8 : &mut Chip8, debugger : &mut Debugger) -> io::Result<String>
+    {
+        loop
+        {
+            match debugger.resume(chip8, Some(RUN_CHUNK))
+            {
+                Ok(StopReason::Limit) =>
+                {
+                    if self.is_interrupted()?
+                    {
+                        return Ok("S02".to_string());
+                    }
+                }
+                result => return Ok(stop_reply(debugger, result)),
+            }
+        }
+    }
+
+    fn is_interrupted(&mut self) -> io::Result<bool>
+    {
+        let mut buffer = [0; 256];
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match result
+        {
+            //The connection is gone, stop so that the next read sees it
+            Ok(0) => Ok(true),
+            Ok(count) =>
+            {
+                self.pending.extend_from_slice(&buffer[.. count]);
+                Ok(self.pending.contains(&INTERRUPT))
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>>
+    {
+        if !self.pending.is_empty()
+        {
+            return Ok(Some(self.pending.remove(0)));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)?
+        {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    //None when the connection is closed
+    fn receive_packet(&mut self) -> io::Result<Option<Packet>>
+    {
+        loop
+        {
+            match self.read_byte()?
+            {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => (),
+                //Acknowledgements and noise
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop
+            {
+                match self.read_byte()?
+                {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut()
+            {
+                *digit = match self.read_byte()? { Some(byte) => byte, None => return Ok(None) };
+            }
+
+            let expected = String::from_utf8_lossy(&checksum).into_owned();
+            if self.no_ack
+            {
+                return Ok(Some(Packet::Command(data)));
+            }
+            if u8::from_str_radix(&expected, 16).ok() == Some(checksum_of(&data))
+            {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(Packet::Command(data)));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data : &[u8]) -> io::Result<()>
+    {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(data)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+}
+
+fn stop_reply(debugger : &Debugger, result : Result<StopReason, Chip8Error>) -> String
+{
+    match result
+    {
+        Ok(StopReason::Done) | Ok(StopReason::Limit) => "S05".to_string(),
+        Ok(StopReason::Breakpoint(_)) => "T05swbreak:;".to_string(),
+        Ok(StopReason::Watchpoint { index, kind, address }) =>
+        {
+            let name = match (debugger.watchpoints()[index].kind, kind)
+            {
+                (WatchKind::ReadWrite, _) => "awatch",
+                (_, MemoryAccessKind::Read) => "rwatch",
+                (_, MemoryAccessKind::Write) => "watch",
+            };
+            format!("T05{}:{:x};", name, address)
+        }
+        //00FD exits the program
+        Ok(StopReason::Halted) => "W00".to_string(),
+        Err(Chip8Error::UnknownOpcode { .. }) => "S04".to_string(),
+        Err(_) => "S0b".to_string(),
+    }
+}
+
+//After a W reply the target is gone and gdb does not expect the session to go on
+fn has_exited(reply : &str) -> bool
+{
+    reply.starts_with('W')
+}
+
+fn read_register(chip8 : &Chip8, index : usize) -> String
+{
+    match index
+    {
+        0 ..= 15 => encode_hex(&[chip8.registers()[index]]),
+        16 => encode_hex(&u16_to_le(chip8.address_register())),
+        17 => encode_hex(&u16_to_le(chip8.program_counter())),
+        STACK_DEPTH_REGISTER => encode_hex(&[chip8.stack().len() as u8]),
+        19 => encode_hex(&[chip8.delay_timer()]),
+        _ => encode_hex(&[chip8.sound_timer()]),
+    }
+}
+
+fn register_size(index : usize) -> usize
+{
+    if index == 16 || index == 17 { 2 } else { 1 }
+}
+
+fn write_register(chip8 : &mut Chip8, index : usize, value : &[u8]) -> Option<()>
+{
+    if value.len() != register_size(index)
+    {
+        return None;
+    }
+    match index
+    {
+        0 ..= 15 => chip8.set_register(index as u8, value[0]),
+        16 => chip8.set_address_register(value[0] as u16 | (value[1] as u16) << 8),
+        17 => chip8.set_program_counter(value[0] as u16 | (value[1] as u16) << 8),
+        STACK_DEPTH_REGISTER => chip8.set_stack_depth(value[0] as usize).ok()?,
+        19 => chip8.set_delay_timer(value[0]),
+        _ => chip8.set_sound_timer(value[0]),
+    }
+    Some(())
+}
+
+fn write_all_registers(chip8 : &mut Chip8, hex : &str) -> Option<()>
+{
+    let data = decode_hex(hex)?;
+    let total : usize = (0 .. REGISTER_COUNT).map(register_size).sum();
+    if data.len() != total
+    {
+        return None;
+    }
+    let offsets : Vec<usize> = (0 .. REGISTER_COUNT).scan(0, |offset, index|
+    {
+        let start = *offset;
+        *offset += register_size(index);
+        Some(start)
+    }).collect();
+    //The stack depth is the only register that can be refused, writing it first leaves the
+    //machine untouched when it is
+    let order = Some(STACK_DEPTH_REGISTER).into_iter().chain((0 .. REGISTER_COUNT).filter(|&index| index != STACK_DEPTH_REGISTER));
+    for index in order
+    {
+        let offset = offsets[index];
+        write_register(chip8, index, &data[offset .. offset + register_size(index)])?;
+    }
+    Some(())
+}
+
+//"addr,length" in hexadecimal
+fn parse_address_length(text : &str) -> Option<(usize, usize)>
+{
+    let mut parts = text.splitn(2, ',');
+    let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+fn parse_resume_address(text : &str) -> Option<u16>
+{
+    if text.is_empty() { None } else { u16::from_str_radix(text, 16).ok() }
+}
+
+fn checksum_of(data : &[u8]) -> u8
+{
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn escape_binary(data : &[u8]) -> Vec<u8>
+{
+    let mut escaped = Vec::with_capacity(data.len());
+    for byte in data
+    {
+        match *byte
+        {
+            b'#' | b'$' | b'}' | b'*' =>
+            {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20);
+            }
+            _ => escaped.push(*byte),
+        }
+    }
+    escaped
+}
+
+fn encode_hex(data : &[u8]) -> String
+{
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text : &str) -> Option<Vec<u8>>
+{
+    if text.len() % 2 == 1
+    {
+        return None;
+    }
+    (0 .. text.len() / 2).map(|i| u8::from_str_radix(text.get(i * 2 .. i * 2 + 2)?, 16).ok()).collect()
+}
+
+fn u16_to_le(value : u16) -> [u8; 2]
+{
+    [value as u8, (value >> 8) as u8]
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::thread;
+    use chip8_core::quirks::Quirks;
+    use chip8_core::random::RandomGenerator;
+
+    //Starts a stub on the given program and returns the client side of the connection
+    fn connect(rom : &'static [u8]) -> (TcpStream, thread::JoinHandle<io::Result<()>>)
+    {
+        let listener = listen(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stub = thread::spawn(move ||
+        {
+            let (stream, _) = listener.accept()?;
+            let mut chip8 = Chip8::new(rom, Quirks::cosmac_vip(), RandomGenerator::seeded(0)).unwrap();
+            GdbStub::new(stream)?.run(&mut chip8, &mut Debugger::default())
+        });
+        (TcpStream::connect(("127.0.0.1", port)).unwrap(), stub)
+    }
+
+    //Sends a packet and returns the data of the reply
+    fn exchange(client : &mut TcpStream, data : &str) -> String
+    {
+        write!(client, "${}#{:02x}", data, checksum_of(data.as_bytes())).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop
+        {
+            client.read_exact(&mut byte).unwrap();
+            match byte[0]
+            {
+                b'+' if reply.is_empty() => (),
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        client.read_exact(&mut checksum).unwrap();
+        client.write_all(b"+").unwrap();
+        String::from_utf8(reply[1 ..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn out_of_range_requests_are_errors()
+    {
+        let (mut client, stub) = connect(&[0x12, 0x00]);
+        assert_eq!(exchange(&mut client, "m200,2"), "1200");
+        assert_eq!(exchange(&mut client, "m200,ffffffffffffffff"), "E01");
+        assert_eq!(exchange(&mut client, "Mffffffffffffffff,1:00"), "E01");
+        assert_eq!(exchange(&mut client, "qXfer:features:read:target.xml:1,ffffffffffffffff"), "E01");
+        assert_eq!(exchange(&mut client, "D"), "OK");
+        stub.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn the_session_ends_when_the_program_exits()
+    {
+        //SCHIP exit
+        let (mut client, stub) = connect(&[0x00, 0xFD]);
+        assert_eq!(exchange(&mut client, "c"), "W00");
+        stub.join().unwrap().unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn refused_register_writes_change_nothing()
+    {
+        let (mut client, stub) = connect(&[0x12, 0x00]);
+        let registers = exchange(&mut client, "g");
+        //V0 = 0x55, PC = 0x300 and a stack deeper than the 12 entries of the VIP
+        let mut refused = registers.clone();
+        refused.replace_range(0 .. 2, "55");
+        refused.replace_range(36 .. 40, "0003");
+        refused.replace_range(40 .. 42, "0d");
+        assert_eq!(exchange(&mut client, &format!("G{}", refused)), "E01");
+        assert_eq!(exchange(&mut client, "g"), registers);
+
+        let mut accepted = refused.clone();
+        accepted.replace_range(40 .. 42, "02");
+        assert_eq!(exchange(&mut client, &format!("G{}", accepted)), "OK");
+        assert_eq!(exchange(&mut client, "g"), accepted);
+        assert_eq!(exchange(&mut client, "D"), "OK");
+        stub.join().unwrap().unwrap();
+    }
+}
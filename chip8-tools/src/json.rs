@@ -0,0 +1,475 @@
+/*
+Just enough JSON for the debug adapter protocol. Objects keep their keys in insertion
+order and numbers are stored as f64, which is exact for every value the protocol uses.
+*/
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json
+{
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json
+{
+    #[allow(dead_code)]
+    pub fn object(members : Vec<(&str, Json)>) -> Json
+    {
+        Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    #[allow(dead_code)]
+    pub fn parse(text : &str) -> Result<Json, String>
+    {
+        let mut parser = Parser { text : text.as_bytes(), position : 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.text.len()
+        {
+            return Err(format!("unexpected data at offset {}", parser.position));
+        }
+        Ok(value)
+    }
+
+    //Null for missing keys and non objects, so lookups can be chained
+    #[allow(dead_code)]
+    pub fn get(&self, key : &str) -> &Json
+    {
+        const NULL : &Json = &Json::Null;
+        match *self
+        {
+            Json::Object(ref members) => members.iter().find(|member| member.0 == key).map_or(NULL, |member| &member.1),
+            _ => NULL,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match *self { Json::String(ref text) => Some(text), _ => None }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_bool(&self) -> Option<bool>
+    {
+        match *self { Json::Bool(value) => Some(value), _ => None }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_f64(&self) -> Option<f64>
+    {
+        match *self { Json::Number(value) => Some(value), _ => None }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_i64(&self) -> Option<i64>
+    {
+        match *self
+        {
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 9007199254740992.0 => Some(value as i64),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_array(&self) -> Option<&[Json]>
+    {
+        match *self { Json::Array(ref values) => Some(values), _ => None }
+    }
+}
+
+impl<'a> From<&'a str> for Json
+{
+    fn from(value : &'a str) -> Json
+    {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json
+{
+    fn from(value : String) -> Json
+    {
+        Json::String(value)
+    }
+}
+
+impl From<bool> for Json
+{
+    fn from(value : bool) -> Json
+    {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json
+{
+    fn from(value : i64) -> Json
+    {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json
+{
+    fn from(values : Vec<Json>) -> Json
+    {
+        Json::Array(values)
+    }
+}
+
+impl fmt::Display for Json
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 9007199254740992.0 => write!(f, "{}", value as i64),
+            Json::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(ref text) => write_string(f, text),
+            Json::Array(ref values) =>
+            {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate()
+                {
+                    if i > 0
+                    {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref members) =>
+            {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate()
+                {
+                    if i > 0
+                    {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f : &mut fmt::Formatter, text : &str) -> fmt::Result
+{
+    write!(f, "\"")?;
+    for c in text.chars()
+    {
+        match c
+        {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+//Arrays and objects nested deeper than this are rejected rather than overflowing the stack
+const MAX_DEPTH : usize = 128;
+
+struct Parser<'a>
+{
+    text : &'a [u8],
+    position : usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn skip_whitespace(&mut self)
+    {
+        while self.position < self.text.len() && (self.text[self.position] as char).is_whitespace()
+        {
+            self.position += 1;
+        }
+    }
+
+    fn error(&self, message : &str) -> String
+    {
+        format!("{} at offset {}", message, self.position)
+    }
+
+    fn expect(&mut self, literal : &str) -> Result<(), String>
+    {
+        if self.text[self.position ..].starts_with(literal.as_bytes())
+        {
+            self.position += literal.len();
+            Ok(())
+        }
+        else
+        {
+            Err(self.error(&format!("expected {}", literal)))
+        }
+    }
+
+    fn value(&mut self, depth : usize) -> Result<Json, String>
+    {
+        self.skip_whitespace();
+        match self.text.get(self.position)
+        {
+            None => Err(self.error("unexpected end")),
+            Some(&b'[') | Some(&b'{') if depth == MAX_DEPTH => Err(self.error("nested too deeply")),
+            Some(&b'n') => self.expect("null").map(|_| Json::Null),
+            Some(&b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(&b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(&b'"') => self.string().map(Json::String),
+            Some(&b'[') =>
+            {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.position) == Some(&b']')
+                {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop
+                {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.text.get(self.position)
+                    {
+                        Some(&b',') => self.position += 1,
+                        Some(&b']') =>
+                        {
+                            self.position += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(self.error("expected , or ]")),
+                    }
+                }
+            }
+            Some(&b'{') =>
+            {
+                self.position += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.position) == Some(&b'}')
+                {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop
+                {
+                    self.skip_whitespace();
+                    if self.text.get(self.position) != Some(&b'"')
+                    {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.text.get(self.position)
+                    {
+                        Some(&b',') => self.position += 1,
+                        Some(&b'}') =>
+                        {
+                            self.position += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.error("expected , or }")),
+                    }
+                }
+            }
+            Some(_) => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String>
+    {
+        let start = self.position;
+        while self.position < self.text.len() && b"+-0123456789.eE".contains(&self.text[self.position])
+        {
+            self.position += 1;
+        }
+        let text = String::from_utf8_lossy(&self.text[start .. self.position]).into_owned();
+        match text.parse::<f64>()
+        {
+            Ok(value) => Ok(Json::Number(value)),
+            Err(_) => Err(format!("'{}' is not a number at offset {}", text, start)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String>
+    {
+        //Skips the opening quote
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop
+        {
+            let byte = match self.text.get(self.position)
+            {
+                Some(byte) => *byte,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.position += 1;
+            match byte
+            {
+                b'"' => break,
+                b'\\' =>
+                {
+                    let escape = match self.text.get(self.position)
+                    {
+                        Some(escape) => *escape,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.position += 1;
+                    let c = match escape
+                    {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("unknown escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String>
+    {
+        let digits = self.text.get(self.position .. self.position + 4).ok_or(self.error("truncated \\u escape"))?;
+        let value = u32::from_str_radix(&String::from_utf8_lossy(digits), 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.position += 4;
+        Ok(value)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, String>
+    {
+        let mut code = self.hex4()?;
+        //UTF-16 surrogate pair, an unpaired half becomes U+FFFD
+        if (0xD800..0xDC00).contains(&code) && self.text[self.position ..].starts_with(b"\\u")
+        {
+            let start = self.position;
+            self.position += 2;
+            let low = self.hex4()?;
+            if (0xDC00..0xE000).contains(&low)
+            {
+                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+            }
+            else
+            {
+                self.position = start;
+            }
+        }
+        Ok(::std::char::from_u32(code).unwrap_or('\u{FFFD}'))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn numbers()
+    {
+        assert_eq!(Json::parse("0"), Ok(Json::Number(0.0)));
+        assert_eq!(Json::parse(" -12 "), Ok(Json::Number(-12.0)));
+        assert_eq!(Json::parse("1.5e2"), Ok(Json::Number(150.0)));
+        assert_eq!(Json::parse("2E-1"), Ok(Json::Number(0.2)));
+        assert_eq!(Json::parse("4096").unwrap().as_i64(), Some(4096));
+        assert_eq!(Json::parse("0.5").unwrap().as_i64(), None);
+        assert!(Json::parse("-").is_err());
+        assert!(Json::parse("1.2.3").is_err());
+        assert!(Json::parse("nan").is_err());
+    }
+
+    #[test]
+    fn escapes()
+    {
+        assert_eq!(Json::parse(r#""a\"b\\c\/d""#), Ok(Json::from("a\"b\\c/d")));
+        assert_eq!(Json::parse(r#""\b\f\n\r\t""#), Ok(Json::from("\u{8}\u{c}\n\r\t")));
+        assert_eq!(Json::parse(r#""\u0041\u00e9""#), Ok(Json::from("A\u{e9}")));
+        assert_eq!(Json::parse("\"\u{e9}\""), Ok(Json::from("\u{e9}")));
+        assert!(Json::parse(r#""\x""#).is_err());
+        assert!(Json::parse(r#""\u12""#).is_err());
+        assert!(Json::parse(r#""\u12g4""#).is_err());
+    }
+
+    #[test]
+    fn surrogate_pairs()
+    {
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Ok(Json::from("\u{1F600}")));
+        assert_eq!(Json::parse(r#""\uD834\uDD1E""#), Ok(Json::from("\u{1D11E}")));
+        //Unpaired halves
+        assert_eq!(Json::parse(r#""\ud83d""#), Ok(Json::from("\u{FFFD}")));
+        assert_eq!(Json::parse(r#""\ude00x""#), Ok(Json::from("\u{FFFD}x")));
+        assert_eq!(Json::parse(r#""\ud83d\u0041""#), Ok(Json::from("\u{FFFD}A")));
+    }
+
+    #[test]
+    fn nesting()
+    {
+        let value = Json::parse(r#"{"a" : [1, {"b" : null}, []], "c" : {}, "d" : true}"#).unwrap();
+        assert_eq!(value.get("a").as_array().map(|values| values.len()), Some(3));
+        assert_eq!(value.get("a").as_array().unwrap()[1].get("b"), &Json::Null);
+        assert_eq!(value.get("c"), &Json::Object(Vec::new()));
+        assert_eq!(value.get("d").as_bool(), Some(true));
+        assert_eq!(value.get("missing").get("deeper"), &Json::Null);
+        assert_eq!(value.to_string(), r#"{"a":[1,{"b":null},[]],"c":{},"d":true}"#);
+
+        let deepest = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(Json::parse(&deepest).is_ok());
+        let too_deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert_eq!(Json::parse(&too_deep), Err(format!("nested too deeply at offset {}", MAX_DEPTH)));
+        assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn trailing_garbage()
+    {
+        assert_eq!(Json::parse("null x"), Err("unexpected data at offset 5".to_string()));
+        assert!(Json::parse("[1] ]").is_err());
+        assert!(Json::parse("{} {}").is_err());
+        assert!(Json::parse("truex").is_err());
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert_eq!(Json::parse(""), Err("unexpected end at offset 0".to_string()));
+        assert!(Json::parse("nul").is_err());
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("[1 2]").is_err());
+        assert!(Json::parse("[").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("{1 : 2}").is_err());
+        assert!(Json::parse("{\"a\" : 1,}").is_err());
+        assert!(Json::parse("\"abc").is_err());
+        assert!(Json::parse("\"abc\\").is_err());
+    }
+}
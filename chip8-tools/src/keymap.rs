@@ -0,0 +1,206 @@
+/*
+Which host keys press which CHIP-8 keys. The original keypad is laid out as
+
+    1 2 3 C
+    4 5 6 D
+    7 8 9 E
+    A 0 B F
+
+and the presets put it under the left hand, or on the numeric keypad. Host keys are either
+virtual keys, named like the window library names them (Key1, Q, Numpad7, ...), which follow
+the layout of the keyboard, or raw scancodes, which follow the physical position of the key.
+Several host keys can press the same CHIP-8 key.
+
+Keymap files hold one statement per line, # starts a comment:
+
+    preset azerty           start from a preset instead of an empty keymap
+    5 = Z Up                CHIP-8 key, then the host keys
+    8 = scancode:31
+*/
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostKey
+{
+    Virtual(String),
+    Scancode(u32),
+}
+
+impl HostKey
+{
+    //scancode:<n> or a virtual key name
+    pub fn parse(text : &str) -> Result<HostKey, String>
+    {
+        if let Some(scancode) = text.strip_prefix("scancode:")
+        {
+            return scancode.parse::<u32>().map(HostKey::Scancode).map_err(|_| format!("'{}' is not a valid scancode", text));
+        }
+        if text.is_empty() || !text.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(format!("'{}' is not a key name", text));
+        }
+        Ok(HostKey::Virtual(text.to_string()))
+    }
+}
+
+//Rows of the original keypad, from top to bottom
+const KEYPAD_ROWS : [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+pub const PRESETS : [&str; 3] = ["qwerty", "azerty", "numpad"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap
+{
+    bindings : Vec<(HostKey, u8)>,
+}
+
+impl Keymap
+{
+    #[allow(dead_code)]
+    pub fn new() -> Keymap
+    {
+        Keymap { bindings : Vec::new() }
+    }
+
+    //1234 / QWER / ASDF / ZXCV
+    #[allow(dead_code)]
+    pub fn qwerty() -> Keymap
+    {
+        Keymap::from_rows(&[["Key1", "Key2", "Key3", "Key4"], ["Q", "W", "E", "R"], ["A", "S", "D", "F"], ["Z", "X", "C", "V"]])
+    }
+
+    //The digits need shift on AZERTY keyboards, so the top row goes by position
+    #[allow(dead_code)]
+    pub fn azerty() -> Keymap
+    {
+        let mut keymap = Keymap::from_rows(&[["Key1", "Key2", "Key3", "Key4"], ["A", "Z", "E", "R"], ["Q", "S", "D", "F"], ["W", "X", "C", "V"]]);
+        for (column, key) in KEYPAD_ROWS[0].iter().enumerate()
+        {
+            keymap.bind(HostKey::Scancode(2 + column as u32), *key);
+        }
+        keymap
+    }
+
+    //The digits on their own value, the operators for A to F
+    #[allow(dead_code)]
+    pub fn numpad() -> Keymap
+    {
+        let mut keymap = Keymap::new();
+        for key in 0 .. 10
+        {
+            keymap.bind(HostKey::Virtual(format!("Numpad{}", key)), key);
+        }
+        let operators = ["NumpadEnter", "Decimal", "Divide", "Multiply", "Subtract", "Add"];
+        for (i, name) in operators.iter().enumerate()
+        {
+            keymap.bind(HostKey::Virtual(name.to_string()), 0xA + i as u8);
+        }
+        keymap
+    }
+
+    #[allow(dead_code)]
+    pub fn from_preset(name : &str) -> Option<Keymap>
+    {
+        match &*name.to_lowercase()
+        {
+            "qwerty" => Some(Keymap::qwerty()),
+            "azerty" => Some(Keymap::azerty()),
+            "numpad" => Some(Keymap::numpad()),
+            _ => None,
+        }
+    }
+
+    fn from_rows(rows : &[[&str; 4]; 4]) -> Keymap
+    {
+        let mut keymap = Keymap::new();
+        for (row, names) in rows.iter().enumerate()
+        {
+            for (column, name) in names.iter().enumerate()
+            {
+                keymap.bind(HostKey::Virtual(name.to_string()), KEYPAD_ROWS[row][column]);
+            }
+        }
+        keymap
+    }
+
+    #[allow(dead_code)]
+    pub fn parse(text : &str) -> Result<Keymap, String>
+    {
+        let mut keymap = Keymap::new();
+        for (i, line) in text.lines().enumerate()
+        {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty()
+            {
+                continue;
+            }
+            let error = |message : String| format!("line {}: {}", i + 1, message);
+
+            if let Some(name) = line.strip_prefix("preset ")
+            {
+                let name = name.trim();
+                keymap = Keymap::from_preset(name).ok_or_else(|| error(format!("unknown preset '{}', the presets are {}", name, PRESETS.join(", "))))?;
+                continue;
+            }
+
+            let mut sides = line.splitn(2, '=');
+            let key_text = sides.next().unwrap().trim();
+            let host_keys = sides.next().ok_or_else(|| error("expected <key> = <host keys>".to_string()))?;
+            let key = match u8::from_str_radix(key_text, 16)
+            {
+                Ok(key) if key <= 0xF && key_text.len() == 1 => key,
+                _ => return Err(error(format!("'{}' is not a CHIP-8 key, they go from 0 to F", key_text))),
+            };
+            if host_keys.trim().is_empty()
+            {
+                return Err(error(format!("no host key for key {:X}", key)));
+            }
+            for host_key in host_keys.split_whitespace()
+            {
+                keymap.bind(HostKey::parse(host_key).map_err(&error)?, key);
+            }
+        }
+        Ok(keymap)
+    }
+
+    #[allow(dead_code)]
+    pub fn load_from_file(path : &Path) -> Result<Keymap, String>
+    {
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+        Keymap::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    //A host key bound twice keeps its last binding
+    #[allow(dead_code)]
+    pub fn bind(&mut self, host_key : HostKey, key : u8)
+    {
+        self.bindings.retain(|binding| binding.0 != host_key);
+        self.bindings.push((host_key, key));
+    }
+
+    #[allow(dead_code)]
+    pub fn bindings(&self) -> &[(HostKey, u8)]
+    {
+        &self.bindings
+    }
+
+    //Virtual keys win over scancodes
+    #[allow(dead_code)]
+    pub fn key_for(&self, scancode : u32, virtual_key : Option<&str>) -> Option<u8>
+    {
+        let find = |host_key : &HostKey| self.bindings.iter().find(|binding| binding.0 == *host_key).map(|binding| binding.1);
+        virtual_key.and_then(|name| find(&HostKey::Virtual(name.to_string()))).or_else(|| find(&HostKey::Scancode(scancode)))
+    }
+}
+
+impl Default for Keymap
+{
+    fn default() -> Keymap
+    {
+        Keymap::qwerty()
+    }
+}
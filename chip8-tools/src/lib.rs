@@ -0,0 +1,200 @@
+/*
+Command line of the emulator and everything it does without a window: rom info,
+assembler, disassembler, debuggers and headless runs. The console, gdb and DAP frontends of
+the debugger and the keymaps live here so that chip8-core stays the emulation alone. The
+desktop frontend parses the same command line and only handles the runs in a window itself.
+*/
+
+extern crate chip8_core;
+extern crate rand;
+
+pub mod cli;
+pub mod session;
+pub mod keymap;
+pub mod debug_console;
+pub mod gdb_stub;
+pub mod json;
+pub mod dap;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use chip8_core::{assembler, audio, chip8, clock, debugger, disassembler, instruction, quirks, savestate, screenshot};
+use session::{Session, start_session, finish_trace, read_rom};
+
+//Every command but the runs in a window
+pub fn run(command : cli::Command) -> Result<(), String>
+{
+    match command
+    {
+        cli::Command::Help =>
+        {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+        cli::Command::Info(path) => print_info(&path),
+        cli::Command::Disassemble(path) => read_rom(&path).map(|rom| print!("{}", disassembler::disassemble(&rom))),
+        cli::Command::Assemble { source, output } => match assembler::assemble_file(&source)
+        {
+            Ok(rom) => File::create(&output).and_then(|mut file| file.write_all(&rom)).map_err(|error| format!("Cannot write {}: {}", output.display(), error)),
+            Err(error) => Err(error.to_string()),
+        },
+        //The rom is given by the launch request of the editor
+        cli::Command::Dap => dap::run_stdio().map_err(|error| format!("Debug adapter stopped: {}", error)),
+        cli::Command::Debug(options) => run_debugger(&options, None),
+        cli::Command::Gdb { options, port } => run_debugger(&options, Some(port)),
+        cli::Command::Run(options) => match options.headless
+        {
+            Some(ref headless) => run_headless(&options, headless),
+            None => Err("Runs with a window are made by the desktop frontend, add --headless to run without one".to_string()),
+        },
+    }
+}
+
+//screen.png becomes screen_000060.png for frame 60
+fn numbered_path(path : &Path, frame : u64) -> PathBuf
+{
+    let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension()
+    {
+        Some(extension) => format!("{}_{:06}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:06}", stem, frame),
+    };
+    path.with_file_name(name)
+}
+
+/*
+Runs without a window and writes the final screen to the output, a .png or .pbm file.
+With --every, the screen is also written every n frames to numbered files.
+With --play, the input of the movie is replayed and it runs for the length of the movie.
+With --wav, the sound is written to a WAV file.
+*/
+pub fn run_headless(options : &cli::RunOptions, headless : &cli::HeadlessOptions) -> Result<(), String>
+{
+    let Session { mut chip8, clock, mut player, .. } = start_session(options)?;
+    let save = |chip8 : &chip8::Chip8, path : &Path| screenshot::save_screen(chip8, path, headless.format).map_err(|error| format!("Cannot write {}: {}", path.display(), error));
+    let mut beeper = match headless.wav
+    {
+        Some(ref path) =>
+        {
+            let output = audio::WavOutput::create(path, audio::DEFAULT_SAMPLE_RATE).map_err(|error| format!("Cannot create {}: {}", path.display(), error))?;
+            Some((audio::Beeper::new(audio::SquareWave::default(), Box::new(output)), path))
+        }
+        None => None,
+    };
+
+    let mut frame = 0;
+    loop
+    {
+        let done = match (headless.frames, headless.instructions)
+        {
+            (_, Some(instructions)) => chip8.instruction_count() >= instructions,
+            (Some(frames), None) => frame >= frames,
+            (None, None) => match player
+            {
+                Some(ref player) => player.is_finished(),
+                None => frame >= 10 * clock::FRAMES_PER_SECOND as u64,
+            },
+        };
+        if done || chip8.is_halted()
+        {
+            break;
+        }
+
+        //The last frame is cut short to stop on the exact instruction
+        let mut instructions_per_frame = clock.instructions_per_frame;
+        if let Some(instructions) = headless.instructions
+        {
+            instructions_per_frame = (instructions - chip8.instruction_count()).min(instructions_per_frame as u64) as u32;
+        }
+        if let Some(ref mut player) = player
+        {
+            player.apply(&mut chip8);
+        }
+        if let Err(error) = chip8.run_frame(instructions_per_frame)
+        {
+            finish_trace(&mut chip8)?;
+            return Err(format!("Emulation stopped: {}", error));
+        }
+        frame += 1;
+
+        if let Some((ref mut beeper, path)) = beeper
+        {
+            beeper.play_chip8_frame(&chip8).map_err(|error| format!("Cannot write {}: {}", path.display(), error))?;
+        }
+        if let Some(every) = headless.every
+        {
+            if frame % every == 0
+            {
+                save(&chip8, &numbered_path(&headless.output, frame))?;
+            }
+        }
+    }
+    finish_trace(&mut chip8)?;
+    save(&chip8, &headless.output)
+}
+
+//The console debugger, or the gdb stub when a port is given
+pub fn run_debugger(options : &cli::RunOptions, port : Option<u16>) -> Result<(), String>
+{
+    let Session { mut chip8, clock, .. } = start_session(options)?;
+    let mut debugger = debugger::Debugger::new(clock.instructions_per_frame);
+    let result = match port
+    {
+        None =>
+        {
+            let stdin = io::stdin();
+            debug_console::run(&mut chip8, &mut debugger, stdin.lock(), &mut io::stdout()).map_err(|error| format!("Debugger stopped: {}", error))
+        }
+        Some(port) => serve_gdb(&mut chip8, &mut debugger, port).map_err(|error| format!("gdb stub stopped: {}", error)),
+    };
+    finish_trace(&mut chip8)?;
+    result
+}
+
+//Waits for one gdb connection and serves it until gdb detaches or the program exits
+fn serve_gdb(chip8 : &mut chip8::Chip8, debugger : &mut debugger::Debugger, port : u16) -> io::Result<()>
+{
+    let listener = gdb_stub::listen(port)?;
+    println!("Waiting for gdb on 127.0.0.1:{}", listener.local_addr()?.port());
+    let (stream, address) = listener.accept()?;
+    println!("gdb connected from {}", address);
+    gdb_stub::GdbStub::new(stream)?.run(chip8, debugger)
+}
+
+//Guesses the platform from the instructions reachable from the entry point
+pub fn print_info(path : &Path) -> Result<(), String>
+{
+    let rom = read_rom(path)?;
+    let instructions = disassembler::reachable_instructions(&rom);
+    let mut uses_super_chip = false;
+    let mut uses_xo_chip = false;
+    let mut unknown = 0;
+    for &(_, instruction) in &instructions
+    {
+        match instruction
+        {
+            instruction::Instruction::ScrollDown(_) | instruction::Instruction::ScrollRight | instruction::Instruction::ScrollLeft |
+            instruction::Instruction::Exit | instruction::Instruction::LowResolution | instruction::Instruction::HighResolution |
+            instruction::Instruction::LdBigFont(_) | instruction::Instruction::StoreFlags(_) | instruction::Instruction::LoadFlags(_) |
+            instruction::Instruction::Drw { n : 0, .. } => uses_super_chip = true,
+            instruction::Instruction::SaveRange { .. } | instruction::Instruction::LoadRange { .. } | instruction::Instruction::LdILong |
+            instruction::Instruction::Plane(_) | instruction::Instruction::Audio | instruction::Instruction::Pitch(_) => uses_xo_chip = true,
+            instruction::Instruction::Unknown(_) => unknown += 1,
+            _ => (),
+        }
+    }
+    let platform = if uses_xo_chip { "xo-chip" } else if uses_super_chip { "schip" } else { "vip" };
+
+    println!("File:              {}", path.display());
+    println!("Size:              {} bytes", rom.len());
+    println!("CRC-32:            {:08X}", savestate::crc32(&rom));
+    println!("Instructions:      {} reachable, {} unknown", instructions.len(), unknown);
+    println!("Suggested quirks:  {}", platform);
+    if rom.len() > quirks::Quirks::cosmac_vip().memory_size - 0x200
+    {
+        println!("The rom is too large for 4 KB of memory and needs --quirks xo-chip");
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+extern crate chip8_tools;
+
+use chip8_tools::cli;
+
+fn main()
+{
+    let result = match cli::parse_args_or_exit()
+    {
+        cli::Command::Run(ref options) if options.headless.is_none() => Err("chip8-tools has no window, add --headless or use the chip8 desktop frontend".to_string()),
+        command => chip8_tools::run(command),
+    };
+    if let Err(error) = result
+    {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,91 @@
+/*
+Interpreter set up from the command line options, shared by the headless runs, the
+debuggers and the desktop frontend.
+*/
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use rand;
+use chip8_core::{chip8, clock, movie, random, savestate, trace};
+use cli;
+
+pub fn read_rom(path : &Path) -> Result<Vec<u8>, String>
+{
+    let mut buffer = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut buffer)).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+    Ok(buffer)
+}
+
+//Interpreter set up from the options, along with the clock and seed it runs with
+pub struct Session
+{
+    pub chip8 : chip8::Chip8,
+    pub clock : clock::Clock,
+    pub seed : u64,
+    pub player : Option<movie::Player>,
+}
+
+//A movie being played decides the seed and the speed
+pub fn start_session(options : &cli::RunOptions) -> Result<Session, String>
+{
+    let movie = match options.play
+    {
+        Some(ref path) => Some(movie::Movie::load_from_file(path).map_err(|error| format!("Cannot load the movie {}: {}", path.display(), error))?),
+        None => None,
+    };
+    if let (Some(movie), Some(seed)) = (movie.as_ref(), options.seed)
+    {
+        if movie.seed != seed
+        {
+            return Err(format!("Cannot play the movie: it was recorded with seed {} but --seed is {}", movie.seed, seed));
+        }
+    }
+    let seed = movie.as_ref().map(|movie| movie.seed).or(options.seed).unwrap_or_else(rand::random);
+    let clock = clock::Clock::new(movie.as_ref().map_or(options.instructions_per_frame, |movie| movie.instructions_per_frame));
+
+    let random = match options.vip_random_page
+    {
+        Some(page) => random::RandomGenerator::cosmac_vip(page),
+        None => random::RandomGenerator::seeded(seed),
+    };
+    let mut chip8 = chip8::Chip8::new(&read_rom(&options.rom)?, options.quirks, random)
+        .map_err(|error| format!("Cannot load the rom: {}", error))?;
+    chip8.set_palette(options.palette);
+    let player = match movie
+    {
+        Some(movie) => Some(movie::Player::new(movie, &chip8).map_err(|error| format!("Cannot play the movie: {}", error))?),
+        None => None,
+    };
+    if let Some(slot) = options.load_slot
+    {
+        savestate::load_from_file(&mut chip8, &savestate::slot_path(&options.rom, slot)).map_err(|error| format!("Cannot load slot {}: {}", slot, error))?;
+    }
+    if let Some(ref trace) = options.trace
+    {
+        let file = io::BufWriter::new(File::create(&trace.path).map_err(|error| format!("Cannot create {}: {}", trace.path.display(), error))?);
+        let cannot_write = |error : io::Error| format!("Cannot write {}: {}", trace.path.display(), error);
+        let output : Box<dyn trace::TraceOutput> = if trace.binary
+        {
+            Box::new(trace::BinaryTrace::new(file).map_err(cannot_write)?)
+        }
+        else
+        {
+            Box::new(trace::TextTrace::new(file).map_err(cannot_write)?)
+        };
+        chip8.set_tracer(Some(trace::Tracer::new(trace.filter, output)));
+    }
+
+    Ok(Session { chip8, clock, seed, player })
+}
+
+//Flushes the trace if there is one
+pub fn finish_trace(chip8 : &mut chip8::Chip8) -> Result<(), String>
+{
+    match chip8.set_tracer(None)
+    {
+        Some(tracer) => tracer.finish().map_err(|error| format!("Cannot write the trace: {}", error)),
+        None => Ok(()),
+    }
+}